@@ -1,6 +1,9 @@
+use serde::Serialize;
 use std::{
     cmp::Ordering,
+    env,
     error::Error,
+    fmt,
     fs::File,
     io::{BufRead, BufReader},
     vec::Vec,
@@ -20,21 +23,112 @@ fn first_unsafe_pair_index<'a>(
         .find_map(|(i, l)| (!is_safe_pair(l.0, l.1, ordering)).then_some(i))
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum UnsafeReason {
+    /// The pair goes in the opposite direction to the rest of the report.
+    Ordering,
+    /// The pair differs by less than one or more than three.
+    StepSize,
+}
+
+impl fmt::Display for UnsafeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnsafeReason::Ordering => write!(f, "ordering"),
+            UnsafeReason::StepSize => write!(f, "step size"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "verdict")]
+enum Verdict {
+    Safe,
+    SafeWithDampener { removed_level: usize },
+    Unsafe,
+}
+
+#[derive(Debug, Serialize)]
+struct Explanation<'a> {
+    levels: &'a [i32],
+    #[serde(flatten)]
+    verdict: Verdict,
+    first_unsafe_pair: Option<usize>,
+    reason: Option<UnsafeReason>,
+}
+
+impl fmt::Display for Explanation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let levels = self
+            .levels
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{levels}: ")?;
+
+        match self.verdict {
+            Verdict::Safe => return write!(f, "safe"),
+            Verdict::SafeWithDampener { .. } => write!(f, "safe with dampener")?,
+            Verdict::Unsafe => write!(f, "unsafe")?,
+        }
+
+        if let (Some(index), Some(reason)) = (self.first_unsafe_pair, self.reason) {
+            write!(
+                f,
+                "; pair {index} ({} {}) failed on {reason}",
+                self.levels[index],
+                self.levels[index + 1]
+            )?;
+        }
+
+        if let Verdict::SafeWithDampener { removed_level } = self.verdict {
+            write!(
+                f,
+                "; dampener removes level {removed_level} ({})",
+                self.levels[removed_level]
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
 struct Report {
     levels: Vec<i32>,
 }
 
 impl Report {
+    fn ordering(&self) -> Option<Ordering> {
+        self.levels.windows(2).next().map(|w| w[0].cmp(&w[1]))
+    }
+
     fn is_safe(&self) -> bool {
-        let ordering = match self.levels.windows(2).next() {
-            Some(w) => w[0].cmp(&w[1]),
-            None => return true,
+        let Some(ordering) = self.ordering() else {
+            return true;
         };
 
         first_unsafe_pair_index(self.levels.iter(), &ordering).is_none()
     }
 
     fn is_safe_with_dampener(&self) -> bool {
+        self.verdict() != Verdict::Unsafe
+    }
+
+    fn verdict(&self) -> Verdict {
+        if self.is_safe() {
+            return Verdict::Safe;
+        }
+
+        self.dampened()
+            .map_or(Verdict::Unsafe, |(_, removed_level)| {
+                Verdict::SafeWithDampener { removed_level }
+            })
+    }
+
+    /// The ordering an unsafe report is made safe in by removing a single level, and that level.
+    fn dampened(&self) -> Option<(Ordering, usize)> {
         let removed_level_at_index_iter = |index| {
             self.levels
                 .iter()
@@ -43,21 +137,56 @@ impl Report {
         };
 
         // Since any level can be removed, we can't make any assumptions about the ordering.
-        [Ordering::Less, Ordering::Greater].into_iter().any(
-            |ordering| match first_unsafe_pair_index(self.levels.iter(), &ordering) {
-                Some(index) => {
-                    // If there is only one bad level and we encounter an unsafe pair, then
-                    // removing one of the two will give us a safe report.
-                    first_unsafe_pair_index(removed_level_at_index_iter(index), &ordering).is_none()
-                        || first_unsafe_pair_index(
-                            removed_level_at_index_iter(index + 1),
-                            &ordering,
-                        )
-                        .is_none()
-                }
-                None => true,
-            },
-        )
+        [Ordering::Less, Ordering::Greater]
+            .into_iter()
+            .find_map(|ordering| {
+                // A safe report would have been caught above, so there is always an unsafe pair.
+                let index = first_unsafe_pair_index(self.levels.iter(), &ordering)?;
+
+                // If there is only one bad level and we encounter an unsafe pair, then
+                // removing one of the two will give us a safe report.
+                [index, index + 1]
+                    .into_iter()
+                    .find(|&removed| {
+                        first_unsafe_pair_index(removed_level_at_index_iter(removed), &ordering)
+                            .is_none()
+                    })
+                    .map(|removed| (ordering, removed))
+            })
+    }
+
+    fn explain(&self) -> Explanation<'_> {
+        // The unsafe pair is found in the ordering of the first pair, unless the dampener made
+        // the report safe going the other way, in which case it's the pair the dampener fixed.
+        let (verdict, ordering) = match (self.is_safe(), self.dampened()) {
+            (true, _) => (Verdict::Safe, self.ordering()),
+            (false, Some((ordering, removed_level))) => {
+                (Verdict::SafeWithDampener { removed_level }, Some(ordering))
+            }
+            (false, None) => (Verdict::Unsafe, self.ordering()),
+        };
+        let first_unsafe_pair =
+            ordering.and_then(|ordering| first_unsafe_pair_index(self.levels.iter(), &ordering));
+
+        // A pair that keeps going the same way as the ordering (or doesn't move at all) can only
+        // have failed on its step size.
+        let reason =
+            first_unsafe_pair.map(
+                |i| match (ordering, self.levels[i].cmp(&self.levels[i + 1])) {
+                    (_, Ordering::Equal) => UnsafeReason::StepSize,
+                    (Some(ordering), pair_ordering) if ordering == pair_ordering => {
+                        UnsafeReason::StepSize
+                    }
+                    _ => UnsafeReason::Ordering,
+                },
+            );
+
+        Explanation {
+            levels: &self.levels,
+            verdict,
+            first_unsafe_pair,
+            reason,
+        }
     }
 }
 
@@ -85,6 +214,20 @@ impl ProcessedInput {
 fn main() -> Result<(), Box<dyn Error>> {
     let input = ProcessedInput::from_buf(BufReader::new(File::open("input/input.txt")?))?;
 
+    // Usage: day_2 [explain [--json]]
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("explain") {
+        let explanations: Vec<_> = input.reports.iter().map(Report::explain).collect();
+        if args.iter().any(|a| a == "--json") {
+            println!("{}", serde_json::to_string_pretty(&explanations)?);
+        } else {
+            for explanation in explanations.iter() {
+                println!("{explanation}");
+            }
+        }
+        return Ok(());
+    }
+
     // Part 1
     let safe_report_count = input.reports.iter().filter(|r| r.is_safe()).count();
     println!("{safe_report_count}");
@@ -100,3 +243,28 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_dampener_ordering() {
+        let report = Report {
+            levels: vec![3, 1, 2, 3, 4],
+        };
+        let explanation = report.explain();
+
+        assert_eq!(
+            explanation.verdict,
+            Verdict::SafeWithDampener { removed_level: 0 }
+        );
+        assert_eq!(explanation.first_unsafe_pair, Some(0));
+        assert_eq!(explanation.reason, Some(UnsafeReason::Ordering));
+        assert_eq!(
+            explanation.to_string(),
+            "3 1 2 3 4: safe with dampener; pair 0 (3 1) failed on ordering; \
+             dampener removes level 0 (3)"
+        );
+    }
+}
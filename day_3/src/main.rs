@@ -1,38 +1,562 @@
-use regex::Regex;
 use std::{
+    env,
     error::Error,
-    fs::File,
-    io::{BufReader, Read},
+    fmt,
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Write},
+    ops::Range,
 };
 
-fn parse_all_muls(input: &str) -> i64 {
-    let re = Regex::new(r"mul\((\d{1,3}),(\d{1,3})\)").unwrap();
-    re.captures_iter(input)
-        .map(|c| {
-            let (_, [left, right]) = c.extract();
-            match (left.parse::<i64>(), right.parse::<i64>()) {
-                (Ok(l), Ok(r)) => l * r,
-                (_, _) => 0,
+/// The most arguments any instruction can take.
+const MAX_ARITY: usize = 4;
+
+/// Every operand is between one and three digits long.
+const MAX_OPERAND_DIGITS: usize = 3;
+
+/// The longest name any instruction can have.
+const MAX_NAME_LEN: usize = 16;
+
+/// Enough to hold the longest possible instruction: its name, the operands with the commas
+/// between them, and the surrounding parentheses.
+const MAX_INSTRUCTION_LEN: usize = MAX_NAME_LEN + MAX_ARITY * (MAX_OPERAND_DIGITS + 1) + 1;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+struct Machine {
+    enabled: bool,
+    sum: i64,
+}
+
+impl Machine {
+    fn new() -> Self {
+        Self {
+            enabled: true,
+            sum: 0,
+        }
+    }
+
+    /// Executes the instruction, returning whether it had an effect.
+    fn execute(&mut self, instruction: &Instruction) -> bool {
+        (instruction.spec.execute)(self, instruction.args())
+    }
+}
+
+struct InstructionSpec {
+    name: &'static str,
+    arity: usize,
+    /// Whether the instruction only changes the machine's state rather than computing anything.
+    control: bool,
+    /// Runs the instruction against the machine, returning whether it was executed.
+    execute: fn(&mut Machine, &[i64]) -> bool,
+}
+
+fn execute_mul(machine: &mut Machine, args: &[i64]) -> bool {
+    if machine.enabled {
+        machine.sum += args[0] * args[1];
+    }
+    machine.enabled
+}
+
+fn execute_do(machine: &mut Machine, _: &[i64]) -> bool {
+    machine.enabled = true;
+    true
+}
+
+fn execute_dont(machine: &mut Machine, _: &[i64]) -> bool {
+    machine.enabled = false;
+    true
+}
+
+const MUL: InstructionSpec = InstructionSpec {
+    name: "mul",
+    arity: 2,
+    control: false,
+    execute: execute_mul,
+};
+
+const DO: InstructionSpec = InstructionSpec {
+    name: "do",
+    arity: 0,
+    control: true,
+    execute: execute_do,
+};
+
+const DONT: InstructionSpec = InstructionSpec {
+    name: "don't",
+    arity: 0,
+    control: true,
+    execute: execute_dont,
+};
+
+const PART_1_INSTRUCTIONS: &[InstructionSpec] = &[MUL];
+const PART_2_INSTRUCTIONS: &[InstructionSpec] = &[MUL, DO, DONT];
+
+#[derive(Clone, Copy)]
+struct Instruction<'a> {
+    spec: &'a InstructionSpec,
+    args: [i64; MAX_ARITY],
+    /// Byte offset of the start of the instruction in the input.
+    offset: usize,
+    /// Length of the instruction in the input, which is longer than its displayed form when
+    /// operands have leading zeros.
+    len: usize,
+}
+
+impl Instruction<'_> {
+    fn args(&self) -> &[i64] {
+        &self.args[..self.spec.arity]
+    }
+}
+
+impl fmt::Display for Instruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self
+            .args()
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(f, "{}({args})", self.spec.name)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// Matching instruction names. `candidates` is a bitmask of the specs whose names start
+    /// with the `len` bytes seen so far.
+    Name { candidates: u32, len: usize },
+    /// Matching the argument list of the spec at `spec`, currently `digits` digits into the
+    /// argument at `arg`.
+    Args {
+        spec: usize,
+        arg: usize,
+        digits: usize,
+    },
+}
+
+enum Step {
+    Continue,
+    Complete,
+    Fail,
+}
+
+/// A byte-at-a-time scanner that recognises instructions from `specs` without allocating.
+///
+/// Bytes that could still be the start of an instruction are held in a fixed-size buffer, so
+/// instructions split across chunk boundaries are recognised once the rest of them arrives.
+/// When a partial match fails, the buffered bytes after its first byte are replayed, which
+/// finds the same leftmost, non-overlapping instructions as scanning the whole input at once.
+struct Scanner<'a> {
+    specs: &'a [InstructionSpec],
+    state: State,
+    args: [i64; MAX_ARITY],
+    pending: [u8; MAX_INSTRUCTION_LEN],
+    pending_len: usize,
+    /// Byte offset in the input of the first pending byte.
+    offset: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(specs: &'a [InstructionSpec]) -> Self {
+        assert!(specs.len() <= u32::BITS as usize, "too many instructions");
+        assert!(specs
+            .iter()
+            .all(|s| s.arity <= MAX_ARITY && !s.name.is_empty() && s.name.len() <= MAX_NAME_LEN));
+
+        Self {
+            specs,
+            state: Self::initial_state(specs),
+            args: [0; MAX_ARITY],
+            pending: [0; MAX_INSTRUCTION_LEN],
+            pending_len: 0,
+            offset: 0,
+        }
+    }
+
+    fn initial_state(specs: &[InstructionSpec]) -> State {
+        State::Name {
+            candidates: ((1u64 << specs.len()) - 1) as u32,
+            len: 0,
+        }
+    }
+
+    /// Scans the next chunk of input, yielding each instruction that ends inside it.
+    fn feed<'s>(&'s mut self, chunk: &'s [u8]) -> Feed<'s, 'a> {
+        Feed {
+            scanner: self,
+            chunk,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Option<Instruction<'a>> {
+        self.pending[self.pending_len] = byte;
+        self.pending_len += 1;
+
+        let mut next = self.pending_len - 1;
+        while next < self.pending_len {
+            match self.step(self.pending[next]) {
+                Step::Continue => next += 1,
+                Step::Complete => {
+                    // A live partial match never contains a `)` before its final byte, so
+                    // completing an instruction always consumes every pending byte.
+                    let State::Args { spec, .. } = self.state else {
+                        unreachable!("only argument lists can complete");
+                    };
+                    let instruction = Instruction {
+                        spec: &self.specs[spec],
+                        args: self.args,
+                        offset: self.offset,
+                        len: self.pending_len,
+                    };
+
+                    self.offset += self.pending_len;
+                    self.pending_len = 0;
+                    self.reset();
+                    return Some(instruction);
+                }
+                Step::Fail => {
+                    // The first pending byte can't start an instruction, so drop it and
+                    // replay the rest in case an instruction starts somewhere inside them.
+                    self.pending.copy_within(1..self.pending_len, 0);
+                    self.pending_len -= 1;
+                    self.offset += 1;
+                    self.reset();
+                    next = 0;
+                }
+            }
+        }
+
+        None
+    }
+
+    fn reset(&mut self) {
+        self.state = Self::initial_state(self.specs);
+        self.args = [0; MAX_ARITY];
+    }
+
+    fn step(&mut self, byte: u8) -> Step {
+        match self.state {
+            State::Name { candidates, len } => {
+                if byte == b'(' {
+                    let complete = (0..self.specs.len())
+                        .find(|&i| candidates & (1 << i) != 0 && self.specs[i].name.len() == len);
+                    return match complete {
+                        Some(spec) => {
+                            self.state = State::Args {
+                                spec,
+                                arg: 0,
+                                digits: 0,
+                            };
+                            Step::Continue
+                        }
+                        None => Step::Fail,
+                    };
+                }
+
+                let candidates = (0..self.specs.len())
+                    .filter(|&i| {
+                        candidates & (1 << i) != 0
+                            && self.specs[i].name.as_bytes().get(len) == Some(&byte)
+                    })
+                    .fold(0, |acc, i| acc | (1 << i));
+                if candidates == 0 {
+                    return Step::Fail;
+                }
+
+                self.state = State::Name {
+                    candidates,
+                    len: len + 1,
+                };
+                Step::Continue
+            }
+            State::Args { spec, arg, digits } => {
+                let arity = self.specs[spec].arity;
+                match byte {
+                    b'0'..=b'9' if arg < arity && digits < MAX_OPERAND_DIGITS => {
+                        self.args[arg] = self.args[arg] * 10 + (byte - b'0') as i64;
+                        self.state = State::Args {
+                            spec,
+                            arg,
+                            digits: digits + 1,
+                        };
+                        Step::Continue
+                    }
+                    b',' if digits > 0 && arg + 1 < arity => {
+                        self.state = State::Args {
+                            spec,
+                            arg: arg + 1,
+                            digits: 0,
+                        };
+                        Step::Continue
+                    }
+                    b')' if arity == 0 || (digits > 0 && arg + 1 == arity) => Step::Complete,
+                    _ => Step::Fail,
+                }
             }
-        })
-        .sum()
+        }
+    }
+}
+
+struct Feed<'s, 'a> {
+    scanner: &'s mut Scanner<'a>,
+    chunk: &'s [u8],
+}
+
+impl<'a> Iterator for Feed<'_, 'a> {
+    type Item = Instruction<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((&byte, rest)) = self.chunk.split_first() {
+            self.chunk = rest;
+            if let Some(instruction) = self.scanner.push(byte) {
+                return Some(instruction);
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Annotation {
+    /// An instruction that was executed.
+    Enabled,
+    /// An instruction that was skipped because of an earlier `don't()`.
+    Disabled,
+    /// An instruction that turns others on or off.
+    Control,
+    /// Something that starts like an instruction but doesn't parse, like `mul(4*`.
+    Corrupt,
+}
+
+impl Annotation {
+    const ALL: [Annotation; 4] = [
+        Annotation::Enabled,
+        Annotation::Disabled,
+        Annotation::Control,
+        Annotation::Corrupt,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Annotation::Enabled => "enabled",
+            Annotation::Disabled => "disabled",
+            Annotation::Control => "control",
+            Annotation::Corrupt => "corrupt",
+        }
+    }
+
+    fn markers(&self) -> (&'static str, &'static str) {
+        match self {
+            Annotation::Enabled => ("[+", "]"),
+            Annotation::Disabled => ("[-", "]"),
+            Annotation::Control => ("[=", "]"),
+            Annotation::Corrupt => ("[!", "]"),
+        }
+    }
+
+    fn colour(&self) -> &'static str {
+        match self {
+            Annotation::Enabled => "\x1b[32m",
+            Annotation::Disabled => "\x1b[33m",
+            Annotation::Control => "\x1b[36m",
+            Annotation::Corrupt => "\x1b[1;31m",
+        }
+    }
+}
+
+const RESET_COLOUR: &str = "\x1b[0m";
+
+fn skip_spaces(input: &[u8]) -> &[u8] {
+    let spaces = input.iter().take_while(|b| **b == b' ').count();
+    &input[spaces..]
+}
+
+/// Returns the length of a near miss of `spec` at the start of `input`: its name and an opening
+/// parenthesis followed by anything other than a valid argument list. The near miss runs for
+/// as long as the input still looks like an argument list, allowing stray spaces and any
+/// number of digits.
+fn near_miss_len(input: &[u8], spec: &InstructionSpec) -> Option<usize> {
+    let rest = skip_spaces(input.strip_prefix(spec.name.as_bytes())?);
+    let mut rest = rest.strip_prefix(b"(")?;
+    let mut end = input.len() - rest.len();
+
+    loop {
+        let arg = skip_spaces(rest);
+        let digits = arg.iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits > 0 {
+            end = input.len() - arg.len() + digits;
+        }
+
+        let after = skip_spaces(&arg[digits..]);
+        match after.first() {
+            Some(b',') => rest = &after[1..],
+            Some(b')') => return Some(input.len() - after.len() + 1),
+            _ => return Some(end),
+        }
+        end = input.len() - rest.len();
+    }
+}
+
+/// Finds every near miss in a stretch of input that contains no valid instructions.
+fn near_misses(
+    gap: &[u8],
+    offset: usize,
+    specs: &[InstructionSpec],
+) -> Vec<(Range<usize>, Annotation)> {
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while pos < gap.len() {
+        match specs
+            .iter()
+            .find_map(|spec| near_miss_len(&gap[pos..], spec))
+        {
+            Some(len) => {
+                result.push((offset + pos..offset + pos + len, Annotation::Corrupt));
+                pos += len;
+            }
+            None => pos += 1,
+        }
+    }
+
+    result
+}
+
+/// Classifies every instruction and near miss in the input, in order of their offsets.
+fn annotate(input: &[u8], specs: &[InstructionSpec]) -> Vec<(Range<usize>, Annotation)> {
+    let mut machine = Machine::new();
+    let mut annotations = Vec::new();
+    let mut gap_start = 0;
+
+    for instruction in Scanner::new(specs).feed(input) {
+        annotations.extend(near_misses(
+            &input[gap_start..instruction.offset],
+            gap_start,
+            specs,
+        ));
+
+        let executed = machine.execute(&instruction);
+        let annotation = match (instruction.spec.control, executed) {
+            (true, _) => Annotation::Control,
+            (false, true) => Annotation::Enabled,
+            (false, false) => Annotation::Disabled,
+        };
+        gap_start = instruction.offset + instruction.len;
+        annotations.push((instruction.offset..gap_start, annotation));
+    }
+    annotations.extend(near_misses(&input[gap_start..], gap_start, specs));
+
+    annotations
+}
+
+fn print_annotated(
+    input: &[u8],
+    annotations: &[(Range<usize>, Annotation)],
+    plain: bool,
+) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    let mut pos = 0;
+    for (range, annotation) in annotations.iter() {
+        let (open, close) = if plain {
+            annotation.markers()
+        } else {
+            (annotation.colour(), RESET_COLOUR)
+        };
+
+        out.write_all(&input[pos..range.start])?;
+        out.write_all(open.as_bytes())?;
+        out.write_all(&input[range.clone()])?;
+        out.write_all(close.as_bytes())?;
+        pos = range.end;
+    }
+    out.write_all(&input[pos..])?;
+    writeln!(out)?;
+
+    for category in Annotation::ALL {
+        let count = annotations.iter().filter(|(_, a)| *a == category).count();
+        writeln!(out, "{}: {count}", category.label())?;
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut input = String::new();
-    BufReader::new(File::open("input/input.txt")?).read_to_string(&mut input)?;
+    // Usage: day_3 [trace | annotate [--plain]]
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mode = args.first().map(String::as_str);
+
+    if mode == Some("annotate") {
+        let input = fs::read("input/input.txt")?;
+        let annotations = annotate(&input, PART_2_INSTRUCTIONS);
+        print_annotated(&input, &annotations, args.iter().any(|a| a == "--plain"))?;
+        return Ok(());
+    }
+
+    let mut reader = File::open("input/input.txt")?;
+    let show_trace = mode == Some("trace");
+
+    let mut part_1_scanner = Scanner::new(PART_1_INSTRUCTIONS);
+    let mut part_2_scanner = Scanner::new(PART_2_INSTRUCTIONS);
+    let mut part_1 = Machine::new();
+    let mut part_2 = Machine::new();
+    let mut trace = Vec::new();
+
+    let mut chunk = [0; CHUNK_SIZE];
+    loop {
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        for instruction in part_1_scanner.feed(&chunk[..len]) {
+            part_1.execute(&instruction);
+        }
+
+        for instruction in part_2_scanner.feed(&chunk[..len]) {
+            if part_2.execute(&instruction) && show_trace {
+                trace.push(instruction);
+            }
+        }
+    }
 
     // Part 1
-    let result = parse_all_muls(&input);
-    println!("{result}");
+    println!("{}", part_1.sum);
 
     // Part 2
-    // Between "do()"s, the first slice before a "don't()" will be enabled
-    let result: i64 = input
-        .split("do()")
-        .map(|s| s.split("don't()").next().map_or(0, parse_all_muls))
-        .sum();
-    println!("{result}");
+    println!("{}", part_2.sum);
+
+    for instruction in trace.iter() {
+        println!("{}: {instruction}", instruction.offset);
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_in_chunks(input: &[u8], specs: &[InstructionSpec], chunk_size: usize) -> i64 {
+        let mut scanner = Scanner::new(specs);
+        let mut machine = Machine::new();
+        for chunk in input.chunks(chunk_size) {
+            for instruction in scanner.feed(chunk) {
+                machine.execute(&instruction);
+            }
+        }
+        machine.sum
+    }
+
+    #[test]
+    fn test_chunk_boundaries() {
+        let input = b"xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))\
+            mumul(1,2)mul(1234,5)mul(12,mul(3,4))dodon't()mul(9,9)do(mul(1,1)";
+
+        for chunk_size in 1..=input.len() {
+            assert_eq!(run_in_chunks(input, PART_1_INSTRUCTIONS, chunk_size), 257);
+            assert_eq!(run_in_chunks(input, PART_2_INSTRUCTIONS, chunk_size), 62);
+        }
+    }
+}
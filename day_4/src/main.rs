@@ -1,31 +1,46 @@
 use std::{
+    collections::HashMap,
+    env,
     error::Error,
-    fs::File,
+    fs::{self, File},
     io::{BufRead, BufReader},
 };
 
-const PATTERNS: [[(i32, i32); 4]; 4] = [
-    [(0, 0), (0, 1), (0, 2), (0, 3)],
-    [(0, 0), (1, 0), (2, 0), (3, 0)],
-    [(0, 0), (1, 1), (2, 2), (3, 3)],
-    [(0, 3), (1, 2), (2, 1), (3, 0)],
-];
-const PATTERN_LETTERS: [u8; 4] = [b'X', b'M', b'A', b'S'];
+/// The X-MAS cross from part 2. Every other orientation is generated from this one.
+const X_MAS: &str = "M.S
+.A.
+M.S";
 
-const PATTERNS_2: [[(i32, i32); 5]; 2] = [
-    [(0, 0), (2, 0), (1, 1), (0, 2), (2, 2)],
-    [(0, 0), (0, 2), (1, 1), (2, 0), (2, 2)],
-];
-const PATTERN_LETTERS_2: [u8; 5] = [b'M', b'M', b'A', b'S', b'S'];
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GridMode {
+    /// Every row must be the same length.
+    Strict,
+    /// Rows can have different lengths, and cells past the end of a short row don't exist.
+    Ragged,
+    /// Every row must be the same length, and the edges wrap around to the opposite side.
+    Toroidal,
+}
+
+impl GridMode {
+    fn from_string(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s {
+            "strict" => Ok(GridMode::Strict),
+            "ragged" => Ok(GridMode::Ragged),
+            "toroidal" => Ok(GridMode::Toroidal),
+            _ => Err(format!("unknown grid mode {s}").into()),
+        }
+    }
+}
 
 struct Grid {
     data: Vec<Vec<u8>>,
     width: i32,
     height: i32,
+    mode: GridMode,
 }
 
 impl Grid {
-    fn from_buf(reader: impl BufRead) -> Result<Self, Box<dyn Error>> {
+    fn from_buf(reader: impl BufRead, mode: GridMode) -> Result<Self, Box<dyn Error>> {
         let data = reader
             .lines()
             .map(|line| line.map(|l| Vec::from(l.as_bytes())))
@@ -37,95 +52,519 @@ impl Grid {
             .ok_or("No rows found")?;
         let height = data.len() as i32;
 
+        if mode != GridMode::Ragged {
+            if let Some(y) = data.iter().position(|row| row.len() as i32 != width) {
+                return Err(format!(
+                    "row {y} has length {} but the grid is {width} wide",
+                    data[y].len()
+                )
+                .into());
+            }
+        }
+
         Ok(Self {
             data,
             width,
             height,
+            mode,
         })
     }
 
-    fn at(&self, x: i32, y: i32) -> Option<u8> {
+    /// Returns the cell a position refers to, wrapping it around the edges in toroidal mode.
+    fn cell(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let (x, y) = match self.mode {
+            GridMode::Toroidal => (x.rem_euclid(self.width), y.rem_euclid(self.height)),
+            GridMode::Strict | GridMode::Ragged => (x, y),
+        };
+
         let x = usize::try_from(x).ok()?;
         let y = usize::try_from(y).ok()?;
-        self.data.get(y).and_then(|row| row.get(x)).cloned()
+        self.data
+            .get(y)
+            .is_some_and(|row| x < row.len())
+            .then_some((x, y))
+    }
+
+    fn at(&self, x: i32, y: i32) -> Option<u8> {
+        self.cell(x, y).map(|(x, y)| self.data[y][x])
+    }
+
+    /// Returns every position a shape of the given size can be placed at with its top-left
+    /// corner, which is anywhere when the edges wrap around.
+    fn origins(&self, width: i32, height: i32) -> impl Iterator<Item = (i32, i32)> {
+        let (max_x, max_y) = match self.mode {
+            GridMode::Toroidal => (self.width - 1, self.height - 1),
+            GridMode::Strict | GridMode::Ragged => (self.width - width, self.height - height),
+        };
+
+        (0..=max_y).flat_map(move |y| (0..=max_x).map(move |x| (x, y)))
     }
 }
 
-fn count_matches(grid: &Grid, x: i32, y: i32) -> usize {
-    PATTERNS
-        .iter()
-        .filter(|pattern| {
-            PATTERN_LETTERS
-                .iter()
-                .zip(pattern.iter())
-                .all(|(letter, point)| {
-                    grid.at(x + point.0, y + point.1)
-                        .is_some_and(|x| x == *letter)
-                })
-                || PATTERN_LETTERS
-                    .iter()
-                    .rev()
-                    .zip(pattern.iter())
-                    .all(|(letter, point)| {
-                        grid.at(x + point.0, y + point.1)
-                            .is_some_and(|x| x == *letter)
-                    })
-        })
-        .count()
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Up,
+    UpRight,
+    Right,
+    DownRight,
+    Down,
+    DownLeft,
+    Left,
+    UpLeft,
 }
 
-fn search(grid: &Grid) -> usize {
-    (-3..=grid.width)
-        .map(|x| {
-            (-3..=grid.height)
-                .map(|y| count_matches(grid, x, y))
-                .sum::<usize>()
-        })
-        .sum()
+impl Direction {
+    const ALL: [Direction; 8] = [
+        Direction::Up,
+        Direction::UpRight,
+        Direction::Right,
+        Direction::DownRight,
+        Direction::Down,
+        Direction::DownLeft,
+        Direction::Left,
+        Direction::UpLeft,
+    ];
+
+    fn delta(&self) -> (i32, i32) {
+        match self {
+            Direction::Up => (0, -1),
+            Direction::UpRight => (1, -1),
+            Direction::Right => (1, 0),
+            Direction::DownRight => (1, 1),
+            Direction::Down => (0, 1),
+            Direction::DownLeft => (-1, 1),
+            Direction::Left => (-1, 0),
+            Direction::UpLeft => (-1, -1),
+        }
+    }
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: HashMap<u8, usize>,
+    /// Index of the word ending at this node, if any.
+    word: Option<usize>,
+}
+
+/// Searches a grid for every word in a dictionary at once.
+///
+/// The words are stored in a trie, so each line out of each cell is walked once no matter how
+/// many words there are, and the walk stops as soon as no word starts with the letters so far.
+struct WordSearch {
+    words: Vec<String>,
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Match<'a> {
+    start: (i32, i32),
+    direction: Direction,
+    word: &'a str,
+}
+
+impl Match<'_> {
+    fn cells(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        let (dx, dy) = self.direction.delta();
+        (0..self.word.len() as i32).map(move |i| (self.start.0 + dx * i, self.start.1 + dy * i))
+    }
+}
+
+impl WordSearch {
+    fn new<S: AsRef<str>>(words: impl IntoIterator<Item = S>) -> Self {
+        let mut search = Self {
+            words: Vec::new(),
+            nodes: vec![TrieNode::default()],
+        };
+
+        for word in words {
+            search.insert(word.as_ref());
+        }
+
+        search
+    }
+
+    fn insert(&mut self, word: &str) {
+        if word.is_empty() {
+            return;
+        }
+
+        let mut node = 0;
+        for letter in word.bytes() {
+            node = match self.nodes[node].children.get(&letter) {
+                Some(&child) => child,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(letter, child);
+                    child
+                }
+            };
+        }
+
+        if self.nodes[node].word.is_none() {
+            self.nodes[node].word = Some(self.words.len());
+            self.words.push(word.to_string());
+        }
+    }
+
+    fn search(&self, grid: &Grid) -> Vec<Match<'_>> {
+        let mut matches = Vec::new();
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                for direction in Direction::ALL {
+                    self.search_from(grid, (x, y), direction, &mut matches);
+                }
+            }
+        }
+
+        matches
+    }
+
+    fn search_from<'a>(
+        &'a self,
+        grid: &Grid,
+        start: (i32, i32),
+        direction: Direction,
+        matches: &mut Vec<Match<'a>>,
+    ) {
+        let (dx, dy) = direction.delta();
+        let (mut x, mut y) = start;
+        let mut node = 0;
+
+        while let Some(&child) = grid
+            .at(x, y)
+            .and_then(|letter| self.nodes[node].children.get(&letter))
+        {
+            node = child;
+            // A one-letter word reads the same in every direction, so it's only matched once.
+            let repeat = (x, y) == start && direction != Direction::ALL[0];
+            if let Some(word) = self.nodes[node].word.filter(|_| !repeat) {
+                matches.push(Match {
+                    start,
+                    direction,
+                    word: &self.words[word],
+                });
+            }
+
+            (x, y) = (x + dx, y + dy);
+        }
+    }
+}
+
+/// A 2D pattern of letters to place on a grid, where `None` cells match any letter.
+///
+/// Stencils are written as rows of text with `.` for the wildcard cells. Rows shorter than the
+/// widest one are padded with wildcards.
+#[derive(Debug, Clone, PartialEq)]
+struct Stencil {
+    cells: Vec<Vec<Option<u8>>>,
+    width: i32,
+    height: i32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    /// Position on the grid of the stencil's top-left cell.
+    origin: (i32, i32),
+    /// Index into `Stencil::orientations` of the orientation that matched.
+    orientation: usize,
 }
 
-fn count_matches_2(grid: &Grid, x: i32, y: i32) -> usize {
-    PATTERNS_2
-        .iter()
-        .filter(|pattern| {
-            PATTERN_LETTERS_2
+impl Stencil {
+    fn from_string(s: &str) -> Result<Self, Box<dyn Error>> {
+        let rows: Vec<&str> = s
+            .lines()
+            .map(str::trim_end)
+            .filter(|row| !row.is_empty())
+            .collect();
+        let width = rows
+            .iter()
+            .map(|row| row.len())
+            .max()
+            .ok_or("Empty stencil")?;
+
+        let cells = rows
+            .iter()
+            .map(|row| {
+                let mut cells: Vec<Option<u8>> = row
+                    .bytes()
+                    .map(|letter| (letter != b'.').then_some(letter))
+                    .collect();
+                cells.resize(width, None);
+                cells
+            })
+            .collect();
+
+        Ok(Self::from_cells(cells))
+    }
+
+    fn from_cells(cells: Vec<Vec<Option<u8>>>) -> Self {
+        let width = cells.first().map_or(0, |row| row.len()) as i32;
+        let height = cells.len() as i32;
+        Self {
+            cells,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the stencil turned 90 degrees clockwise.
+    fn rotated(&self) -> Self {
+        Self::from_cells(
+            (0..self.width as usize)
+                .map(|x| self.cells.iter().rev().map(|row| row[x]).collect())
+                .collect(),
+        )
+    }
+
+    /// Returns the stencil mirrored left to right.
+    fn reflected(&self) -> Self {
+        Self::from_cells(
+            self.cells
                 .iter()
-                .zip(pattern.iter())
-                .all(|(letter, point)| {
-                    grid.at(x + point.0, y + point.1)
-                        .is_some_and(|x| x == *letter)
-                })
-                || PATTERN_LETTERS_2
-                    .iter()
-                    .rev()
-                    .zip(pattern.iter())
-                    .all(|(letter, point)| {
-                        grid.at(x + point.0, y + point.1)
-                            .is_some_and(|x| x == *letter)
-                    })
+                .map(|row| row.iter().rev().copied().collect())
+                .collect(),
+        )
+    }
+
+    /// Returns every distinct rotation and reflection of the stencil, starting with itself.
+    fn orientations(&self) -> Vec<Stencil> {
+        let mut orientations: Vec<Stencil> = Vec::new();
+        for start in [self.clone(), self.reflected()] {
+            let mut stencil = start;
+            for _ in 0..4 {
+                let next = stencil.rotated();
+                if !orientations.contains(&stencil) {
+                    orientations.push(stencil);
+                }
+                stencil = next;
+            }
+        }
+
+        orientations
+    }
+
+    /// Returns the positions of the stencil's letters when its top-left cell is at `origin`.
+    fn letter_cells(&self, origin: (i32, i32)) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.cells.iter().zip(origin.1..).flat_map(move |(row, y)| {
+            row.iter()
+                .zip(origin.0..)
+                .filter_map(move |(cell, x)| cell.map(|_| (x, y)))
         })
-        .count()
-}
+    }
 
-fn search_2(grid: &Grid) -> usize {
-    (-4..=grid.width)
-        .map(|x| {
-            (-4..=grid.height)
-                .map(|y| count_matches_2(grid, x, y))
-                .sum::<usize>()
+    fn matches_at(&self, grid: &Grid, x: i32, y: i32) -> bool {
+        self.cells.iter().zip(y..).all(|(row, y)| {
+            row.iter().zip(x..).all(|(cell, x)| {
+                grid.at(x, y)
+                    .is_some_and(|letter| cell.is_none_or(|c| c == letter))
+            })
         })
-        .sum()
+    }
+
+    fn placements(&self, grid: &Grid) -> Vec<Placement> {
+        let mut placements = Vec::new();
+        for (orientation, stencil) in self.orientations().iter().enumerate() {
+            for (x, y) in grid.origins(stencil.width, stencil.height) {
+                if stencil.matches_at(grid, x, y) {
+                    placements.push(Placement {
+                        origin: (x, y),
+                        orientation,
+                    });
+                }
+            }
+        }
+
+        placements
+    }
+}
+
+const HIGHLIGHT_COLOUR: &str = "\x1b[1;33m";
+const DIM_COLOUR: &str = "\x1b[2m";
+const RESET_COLOUR: &str = "\x1b[0m";
+
+/// Colours for cells covered by one, two, three, four, and five or more matches.
+const HEAT_COLOURS: [&str; 5] = ["\x1b[34m", "\x1b[36m", "\x1b[32m", "\x1b[33m", "\x1b[1;31m"];
+
+/// The number of matches covering each cell of a grid.
+struct Coverage<'a> {
+    grid: &'a Grid,
+    counts: Vec<Vec<usize>>,
+}
+
+impl<'a> Coverage<'a> {
+    fn new(grid: &'a Grid) -> Self {
+        Self {
+            grid,
+            counts: grid.data.iter().map(|row| vec![0; row.len()]).collect(),
+        }
+    }
+
+    fn add(&mut self, cells: impl IntoIterator<Item = (i32, i32)>) {
+        for (x, y) in cells {
+            if let Some((x, y)) = self.grid.cell(x, y) {
+                self.counts[y][x] += 1;
+            }
+        }
+    }
+
+    /// Renders the grid with covered cells highlighted and the rest dimmed. As a heatmap, each
+    /// covered cell shows its count instead of its letter, coloured by how high it is.
+    fn render(&self, heatmap: bool) -> String {
+        let mut output = String::new();
+        for (row, counts) in self.grid.data.iter().zip(self.counts.iter()) {
+            for (&letter, &count) in row.iter().zip(counts.iter()) {
+                let (colour, cell) = match (count, heatmap) {
+                    (0, _) => (DIM_COLOUR, letter as char),
+                    (_, false) => (HIGHLIGHT_COLOUR, letter as char),
+                    (_, true) => (
+                        HEAT_COLOURS[count.min(HEAT_COLOURS.len()) - 1],
+                        char::from_digit(count as u32, 10).unwrap_or('+'),
+                    ),
+                };
+                output.push_str(colour);
+                output.push(cell);
+                output.push_str(RESET_COLOUR);
+            }
+            output.push('\n');
+        }
+
+        output
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // Usage: day_4 [words <dictionary file> | stencil <stencil file> [--list]
+    //              | render [--cross] [--heatmap]] [--mode strict|ragged|toroidal]
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mode = match args.iter().position(|a| a == "--mode") {
+        Some(i) => GridMode::from_string(args.get(i + 1).ok_or("missing grid mode")?)?,
+        None => GridMode::Ragged,
+    };
+
     let reader = BufReader::new(File::open("input/input.txt")?);
-    let grid = Grid::from_buf(reader)?;
+    let grid = Grid::from_buf(reader, mode)?;
+
+    if args.first().map(String::as_str) == Some("render") {
+        let mut coverage = Coverage::new(&grid);
+        if args.iter().any(|a| a == "--cross") {
+            let stencil = Stencil::from_string(X_MAS)?;
+            let orientations = stencil.orientations();
+            for p in stencil.placements(&grid) {
+                coverage.add(orientations[p.orientation].letter_cells(p.origin));
+            }
+        } else {
+            for m in WordSearch::new(["XMAS"]).search(&grid) {
+                coverage.add(m.cells());
+            }
+        }
+        print!("{}", coverage.render(args.iter().any(|a| a == "--heatmap")));
+        return Ok(());
+    }
+
+    if args.first().map(String::as_str) == Some("stencil") {
+        let stencil = Stencil::from_string(&fs::read_to_string(
+            args.get(1).ok_or("missing stencil file")?,
+        )?)?;
+        let placements = stencil.placements(&grid);
+        if args.iter().any(|a| a == "--list") {
+            for p in placements.iter() {
+                println!(
+                    "{},{} orientation {}",
+                    p.origin.0, p.origin.1, p.orientation
+                );
+            }
+        }
+        println!("{}", placements.len());
+        return Ok(());
+    }
 
-    let result = search(&grid);
+    if args.first().map(String::as_str) == Some("words") {
+        let dictionary = fs::read_to_string(args.get(1).ok_or("missing dictionary file")?)?;
+        let word_search = WordSearch::new(dictionary.split_whitespace());
+        for m in word_search.search(&grid) {
+            println!("{},{} {:?} {}", m.start.0, m.start.1, m.direction, m.word);
+        }
+        return Ok(());
+    }
+
+    let result = WordSearch::new(["XMAS"]).search(&grid).len();
     println!("{result}");
 
-    let result = search_2(&grid);
+    let result = Stencil::from_string(X_MAS)?.placements(&grid).len();
     println!("{result}");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "MMMSXXMASM
+MSAMXMSMSA
+AMXSXMAAMM
+MSAMASMSMX
+XMASAMXAMM
+XXAMMXXAMA
+SMSMSASXSS
+SAXAMASAAA
+MAMMMXMMMM
+MXMXAXMASX";
+
+    fn grid(s: &str, mode: GridMode) -> Grid {
+        Grid::from_buf(s.as_bytes(), mode).unwrap()
+    }
+
+    #[test]
+    fn test_sample() {
+        let grid = grid(SAMPLE, GridMode::Strict);
+
+        assert_eq!(WordSearch::new(["XMAS"]).search(&grid).len(), 18);
+        assert_eq!(
+            Stencil::from_string(X_MAS).unwrap().placements(&grid).len(),
+            9
+        );
+    }
+
+    #[test]
+    fn test_strict_rejects_ragged_rows() {
+        assert!(Grid::from_buf("XMAS\nXM".as_bytes(), GridMode::Strict).is_err());
+        assert!(Grid::from_buf("XMAS\nXM".as_bytes(), GridMode::Ragged).is_ok());
+    }
+
+    #[test]
+    fn test_toroidal() {
+        let input = "ASXM\n....";
+
+        assert_eq!(
+            WordSearch::new(["XMAS"])
+                .search(&grid(input, GridMode::Ragged))
+                .len(),
+            0
+        );
+        let grid = grid(input, GridMode::Toroidal);
+        let word_search = WordSearch::new(["XMAS"]);
+        let matches = word_search.search(&grid);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].start, (2, 0));
+        assert_eq!(matches[0].direction, Direction::Right);
+        assert_eq!(grid.cell(5, -1), Some((1, 1)));
+    }
+
+    #[test]
+    fn test_symmetric_stencil_orientations() {
+        // Mirroring the cross gives one of its rotations, and a single letter looks the same
+        // every way round.
+        assert_eq!(Stencil::from_string(X_MAS).unwrap().orientations().len(), 4);
+        assert_eq!(Stencil::from_string("A").unwrap().orientations().len(), 1);
+    }
+
+    #[test]
+    fn test_one_letter_word() {
+        let grid = grid(SAMPLE, GridMode::Strict);
+        let xs = SAMPLE.bytes().filter(|&b| b == b'X').count();
+
+        assert_eq!(WordSearch::new(["X"]).search(&grid).len(), xs);
+    }
+}
@@ -1,79 +1,459 @@
 use std::{
-    collections::{HashMap, HashSet},
-    fs,
+    cell::OnceCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    env,
+    error::Error,
+    fmt, fs,
 };
 
+/// Parses a rule written as `X|Y`, meaning page X must come before page Y.
+fn parse_rule(s: &str) -> Result<(i32, i32), Box<dyn Error>> {
+    let (before, after) = s.split_once('|').ok_or("rule is missing a '|'")?;
+    Ok((before.trim().parse()?, after.trim().parse()?))
+}
+
+#[derive(Default)]
 struct OrderingRules {
     after_to_before: HashMap<i32, HashSet<i32>>,
+    /// Every page that must come before each page, directly or through other rules. It's built
+    /// on the first transitive query and thrown away whenever the rules change.
+    closure: OnceCell<HashMap<i32, HashSet<i32>>>,
 }
 
 impl OrderingRules {
-    fn from_string(string: &str) -> Self {
-        let rules_iter = string.lines().map(|line| {
-            let mut split_iter = line.split('|');
-            (
-                split_iter.next().unwrap().parse::<i32>().unwrap(),
-                split_iter.next().unwrap().parse::<i32>().unwrap(),
-            )
-        });
+    fn from_string(string: &str) -> Result<Self, Box<dyn Error>> {
+        let mut rules = Self::default();
+        for line in string.lines() {
+            let (before, after) = parse_rule(line)?;
+            rules.add_rule(before, after);
+        }
+
+        Ok(rules)
+    }
 
-        let mut after_to_before: HashMap<i32, HashSet<i32>> = HashMap::new();
-        for rule in rules_iter {
-            after_to_before.entry(rule.1).or_default().insert(rule.0);
+    fn add_rule(&mut self, before: i32, after: i32) {
+        if self
+            .after_to_before
+            .entry(after)
+            .or_default()
+            .insert(before)
+        {
+            self.closure.take();
         }
+    }
 
-        Self {
-            after_to_before,
+    /// Removes the rule, returning whether it existed.
+    fn remove_rule(&mut self, before: i32, after: i32) -> bool {
+        let removed = self
+            .after_to_before
+            .get_mut(&after)
+            .is_some_and(|befores| befores.remove(&before));
+        if removed {
+            self.closure.take();
         }
+
+        removed
+    }
+
+    /// Whether a rule directly says `before` must come before `after`.
+    fn must_precede(&self, before: i32, after: i32) -> bool {
+        self.after_to_before
+            .get(&after)
+            .is_some_and(|befores| befores.contains(&before))
+    }
+
+    /// The pages a rule directly says must come before `after`.
+    fn befores(&self, after: i32) -> impl Iterator<Item = i32> + '_ {
+        self.after_to_before
+            .get(&after)
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// Whether a chain of rules says `before` must come before `after`.
+    fn must_precede_transitively(&self, before: i32, after: i32) -> bool {
+        self.closure
+            .get_or_init(|| self.compute_closure())
+            .get(&after)
+            .is_some_and(|befores| befores.contains(&before))
+    }
+
+    fn compute_closure(&self) -> HashMap<i32, HashSet<i32>> {
+        self.after_to_before
+            .keys()
+            .map(|&after| {
+                let mut befores = HashSet::new();
+                let mut stack = vec![after];
+                while let Some(page) = stack.pop() {
+                    for &before in self.after_to_before.get(&page).into_iter().flatten() {
+                        if befores.insert(before) {
+                            stack.push(before);
+                        }
+                    }
+                }
+                (after, befores)
+            })
+            .collect()
     }
 }
 
+/// Where each page is in an update.
+fn positions(update: &[i32]) -> HashMap<i32, usize> {
+    update
+        .iter()
+        .enumerate()
+        .map(|(i, &page)| (page, i))
+        .collect()
+}
+
+/// Checks that no rule puts a later page of the update before an earlier one.
+///
+/// Rather than checking every pair of pages, each page only looks up the pages its rules say
+/// must come before it, so pages the rules leave unordered cost nothing.
 fn update_is_correct(update: &[i32], rules: &OrderingRules) -> bool {
-    update.iter().enumerate().all(|(i, p0)| {
-        update[i..update.len()].iter().all(|p1| {
-            !rules
-                .after_to_before
-                .get(p0)
-                .is_some_and(|after_p0| after_p0.contains(p1))
-        })
+    let positions = positions(update);
+    update.iter().enumerate().all(|(i, &page)| {
+        rules
+            .befores(page)
+            .all(|before| positions.get(&before).is_none_or(|&j| j < i))
     })
 }
 
-fn sorted_update(update: &[i32], rules: &OrderingRules) -> Vec<i32> {
-    let mut result = update.to_vec();
-    sort_update(&mut result, rules);
+#[derive(Debug, PartialEq)]
+enum OrderError {
+    /// The rules between the pages of an update form a cycle, listed so that each page must
+    /// come before the next one and the last page must come before the first.
+    Cycle(Vec<i32>),
+    /// The rules don't decide which of these pages comes next, so there is more than one
+    /// valid order.
+    Ambiguous(Vec<i32>),
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::Cycle(pages) => {
+                let rules = pages
+                    .iter()
+                    .zip(pages.iter().cycle().skip(1))
+                    .map(|(before, after)| format!("{before}|{after}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "rules form a cycle: {rules}")
+            }
+            OrderError::Ambiguous(pages) => {
+                let pages = pages
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "order is ambiguous between pages {pages}")
+            }
+        }
+    }
+}
+
+impl Error for OrderError {}
+
+/// Orders the pages of an update with a topological sort of the rules between them, failing
+/// if the rules contradict each other or don't pin down a single order. A cycle is reported
+/// over an ambiguity, as the sort carries on past the first choice it has to make to look for
+/// one.
+///
+/// Like `update_is_correct`, this only visits the rules about the update's own pages, so it
+/// takes time in proportion to the pages and those rules rather than every pair of pages.
+fn sorted_update(update: &[i32], rules: &OrderingRules) -> Result<Vec<i32>, OrderError> {
+    let positions = positions(update);
+
+    // The positions of the pages that must come before and after each page. The ones before
+    // are kept in update order, so the same cycle is always reported.
+    let mut befores: Vec<Vec<usize>> = update
+        .iter()
+        .map(|&page| {
+            rules
+                .befores(page)
+                .filter_map(|before| positions.get(&before).copied())
+                .collect()
+        })
+        .collect();
+    let mut afters = vec![Vec::new(); update.len()];
+    for (j, befores) in befores.iter_mut().enumerate() {
+        befores.sort_unstable();
+        for &i in befores.iter() {
+            afters[i].push(j);
+        }
+    }
+
+    let mut in_degree: Vec<usize> = befores.iter().map(Vec::len).collect();
+    let mut ready: BTreeSet<usize> = (0..update.len()).filter(|&i| in_degree[i] == 0).collect();
+    let mut placed = vec![false; update.len()];
+    let mut result = Vec::with_capacity(update.len());
+    let mut ambiguous = None;
+
+    while result.len() < update.len() {
+        if ready.len() > 1 && ambiguous.is_none() {
+            ambiguous = Some(ready.iter().map(|&i| update[i]).collect());
+        }
+
+        match ready.pop_first() {
+            None => {
+                // Every remaining page has a remaining page that must precede it, so walking
+                // backwards through them must eventually revisit one.
+                let mut path = vec![(0..update.len()).find(|&i| !placed[i]).unwrap()];
+                loop {
+                    let page = *path.last().unwrap();
+                    let before = *befores[page].iter().find(|&&i| !placed[i]).unwrap();
+
+                    if let Some(start) = path.iter().position(|&p| p == before) {
+                        let cycle = path[start..].iter().rev().map(|&i| update[i]).collect();
+                        return Err(OrderError::Cycle(cycle));
+                    }
+                    path.push(before);
+                }
+            }
+            Some(next) => {
+                placed[next] = true;
+                result.push(update[next]);
+                for &j in afters[next].iter() {
+                    in_degree[j] -= 1;
+                    if in_degree[j] == 0 {
+                        ready.insert(j);
+                    }
+                }
+            }
+        }
+    }
+
+    match ambiguous {
+        Some(pages) => Err(OrderError::Ambiguous(pages)),
+        None => Ok(result),
+    }
+}
+
+/// A rule `before|after` that an update breaks by putting `after` first.
+#[derive(Debug, PartialEq)]
+struct Violation {
+    before: i32,
+    after: i32,
+    before_pos: usize,
+    after_pos: usize,
+}
+
+/// Moves `page` to just after `after`, or to the front of the update if there is no `after`.
+#[derive(Debug, PartialEq)]
+struct Move {
+    page: i32,
+    after: Option<i32>,
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.after {
+            Some(after) => write!(f, "move {} after {after}", self.page),
+            None => write!(f, "move {} to the front", self.page),
+        }
+    }
+}
+
+struct UpdateReport<'a> {
+    update: &'a [i32],
+    violations: Vec<Violation>,
+    /// The fewest moves that fix the update, and the order they produce.
+    fix: Result<(Vec<Move>, Vec<i32>), OrderError>,
+}
+
+fn violations(update: &[i32], rules: &OrderingRules) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for (after_pos, after) in update.iter().enumerate() {
+        for (before_pos, before) in update.iter().enumerate().skip(after_pos + 1) {
+            if rules.must_precede(*before, *after) {
+                violations.push(Violation {
+                    before: *before,
+                    after: *after,
+                    before_pos,
+                    after_pos,
+                });
+            }
+        }
+    }
+
+    violations
+}
+
+/// Finds the fewest moves that turn `update` into `sorted`.
+///
+/// The longest run of pages that are already in sorted order relative to each other can stay
+/// where they are, and every other page has to move. Moving those in sorted order, each to just
+/// after the page that precedes it in `sorted`, rebuilds the sorted order around them.
+fn fewest_moves(update: &[i32], sorted: &[i32]) -> Vec<Move> {
+    let ranks: Vec<usize> = update
+        .iter()
+        .map(|page| sorted.iter().position(|p| p == page).unwrap())
+        .collect();
+
+    // Longest increasing subsequence of the ranks, remembering where each one came from.
+    let mut lengths = vec![1; ranks.len()];
+    let mut previous = vec![None; ranks.len()];
+    for i in 0..ranks.len() {
+        for j in 0..i {
+            if ranks[j] < ranks[i] && lengths[j] + 1 > lengths[i] {
+                lengths[i] = lengths[j] + 1;
+                previous[i] = Some(j);
+            }
+        }
+    }
+
+    let mut kept = vec![false; sorted.len()];
+    let mut next = (0..ranks.len()).max_by_key(|&i| lengths[i]);
+    while let Some(i) = next {
+        kept[ranks[i]] = true;
+        next = previous[i];
+    }
+
+    (0..sorted.len())
+        .filter(|&rank| !kept[rank])
+        .map(|rank| Move {
+            page: sorted[rank],
+            after: rank.checked_sub(1).map(|r| sorted[r]),
+        })
+        .collect()
+}
+
+fn explain_update<'a>(update: &'a [i32], rules: &OrderingRules) -> UpdateReport<'a> {
+    let fix = sorted_update(update, rules).map(|sorted| (fewest_moves(update, &sorted), sorted));
+    UpdateReport {
+        update,
+        violations: violations(update, rules),
+        fix,
+    }
+}
+
+fn join(pages: &[i32]) -> String {
+    pages
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl fmt::Display for UpdateReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", join(self.update))?;
+        if self.violations.is_empty() {
+            return writeln!(f, "correct");
+        }
+
+        writeln!(f, "incorrect")?;
+        for v in self.violations.iter() {
+            writeln!(
+                f,
+                "  violates {}|{} ({} at {}, {} at {})",
+                v.before, v.after, v.before, v.before_pos, v.after, v.after_pos
+            )?;
+        }
+
+        match &self.fix {
+            Ok((moves, sorted)) => {
+                writeln!(f, "  fewest moves: {}", moves.len())?;
+                for m in moves.iter() {
+                    writeln!(f, "    {m}")?;
+                }
+                writeln!(f, "  corrected: {}", join(sorted))
+            }
+            Err(e) => writeln!(f, "  can't be corrected: {e}"),
+        }
+    }
+}
 
-    result
+/// The rules as a graph, with an edge from each page to every page that must come after it.
+struct RuleGraph {
+    edges: BTreeMap<i32, BTreeSet<i32>>,
 }
 
-fn sort_update(update: &mut [i32], rules: &OrderingRules) {
-    if update.len() <= 1 {
-        return;
+impl RuleGraph {
+    /// Builds the graph of every rule, or only the rules between the given pages.
+    fn from_rules(rules: &OrderingRules, pages: Option<&[i32]>) -> Self {
+        let included = |page: &i32| pages.is_none_or(|pages| pages.contains(page));
+
+        let mut edges: BTreeMap<i32, BTreeSet<i32>> = BTreeMap::new();
+        for page in pages.unwrap_or_default() {
+            edges.entry(*page).or_default();
+        }
+        for (after, befores) in rules.after_to_before.iter().filter(|(a, _)| included(a)) {
+            for before in befores.iter().filter(|b| included(b)) {
+                edges.entry(*before).or_default().insert(*after);
+                edges.entry(*after).or_default();
+            }
+        }
+
+        Self { edges }
+    }
+
+    /// Whether `to` can be reached from `from` without following the edge between them.
+    fn reachable_indirectly(&self, from: i32, to: i32) -> bool {
+        let mut seen = HashSet::from([from]);
+        let mut stack: Vec<i32> = self.edges[&from]
+            .iter()
+            .copied()
+            .filter(|&p| p != to)
+            .collect();
+
+        while let Some(page) = stack.pop() {
+            if page == to {
+                return true;
+            }
+            if seen.insert(page) {
+                stack.extend(self.edges[&page].iter().copied());
+            }
+        }
+
+        false
     }
-    let pivot = *update.last().unwrap();
 
-    let mut final_pivot_index = 0;
-    for i in 0..update.len() {
-        let page = update[i];
-        if rules.after_to_before.get(&pivot).is_some_and(|a| a.contains(&page)) {
-            update.swap(final_pivot_index, i);
-            final_pivot_index += 1;
+    /// Removes every edge that is implied by a longer path, keeping the same reachability.
+    ///
+    /// Edges are dropped one at a time, so this also works when the rules contain cycles, where
+    /// the reduction isn't unique.
+    fn transitive_reduction(mut self) -> Self {
+        let all_edges: Vec<(i32, i32)> = self
+            .edges
+            .iter()
+            .flat_map(|(before, afters)| afters.iter().map(|after| (*before, *after)))
+            .collect();
+
+        for (before, after) in all_edges {
+            if self.reachable_indirectly(before, after) {
+                self.edges.get_mut(&before).unwrap().remove(&after);
+            }
         }
+
+        self
     }
 
-    update.swap(final_pivot_index, update.len() - 1);
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph rules {\n");
+        for (before, afters) in self.edges.iter() {
+            dot.push_str(&format!("    {before};\n"));
+            for after in afters.iter() {
+                dot.push_str(&format!("    {before} -> {after};\n"));
+            }
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
 
-    let left_range = 0..final_pivot_index;
-    let right_range = (final_pivot_index+1)..(update.len());
-    sort_update(&mut update[left_range], rules);
-    sort_update(&mut update[right_range], rules);
+    fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.edges)
+    }
 }
 
-fn main() {
-    let input = fs::read_to_string("input/input.txt").unwrap();
+fn main() -> Result<(), Box<dyn Error>> {
+    let input = fs::read_to_string("input/input.txt")?;
     let (rules_string, update_string) = input.split_once("\n\n").unwrap();
 
-    let rules = OrderingRules::from_string(rules_string);
+    let mut rules = OrderingRules::from_string(rules_string)?;
     let updates = update_string
         .lines()
         .map(|line| {
@@ -83,6 +463,76 @@ fn main() {
         })
         .collect::<Vec<_>>();
 
+    // Usage: day_5 [explain | graph [--format dot|json] [--update <index>] [--reduce]
+    //              | query <before> <after>] [--add-rule X|Y]... [--remove-rule X|Y]...
+    let args: Vec<String> = env::args().skip(1).collect();
+    let flag_value = |flag: &str| {
+        args.iter()
+            .position(|a| a == flag)
+            .map(|i| args.get(i + 1).ok_or(format!("missing value for {flag}")))
+            .transpose()
+    };
+
+    for (flag, value) in args.iter().zip(args.iter().skip(1)) {
+        match flag.as_str() {
+            "--add-rule" => {
+                let (before, after) = parse_rule(value)?;
+                rules.add_rule(before, after);
+            }
+            "--remove-rule" => {
+                let (before, after) = parse_rule(value)?;
+                if !rules.remove_rule(before, after) {
+                    return Err(format!("no rule {value} to remove").into());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if args.first().map(String::as_str) == Some("query") {
+        let page = |i: usize| -> Result<i32, Box<dyn Error>> {
+            Ok(args.get(i).ok_or("missing page")?.parse()?)
+        };
+        let (before, after) = (page(1)?, page(2)?);
+        println!("direct: {}", rules.must_precede(before, after));
+        println!(
+            "transitive: {}",
+            rules.must_precede_transitively(before, after)
+        );
+        return Ok(());
+    }
+
+    if args.first().map(String::as_str) == Some("graph") {
+        let pages = match flag_value("--update")? {
+            Some(index) => Some(
+                updates
+                    .get(index.parse::<usize>()?)
+                    .ok_or("update index out of range")?
+                    .as_slice(),
+            ),
+            None => None,
+        };
+
+        let mut graph = RuleGraph::from_rules(&rules, pages);
+        if args.iter().any(|a| a == "--reduce") {
+            graph = graph.transitive_reduction();
+        }
+
+        match flag_value("--format")?.map_or("dot", String::as_str) {
+            "dot" => print!("{}", graph.to_dot()),
+            "json" => println!("{}", graph.to_json()?),
+            format => return Err(format!("unknown format {format}").into()),
+        }
+        return Ok(());
+    }
+
+    if args.first().map(String::as_str) == Some("explain") {
+        for update in updates.iter() {
+            print!("{}", explain_update(update, &rules));
+        }
+        return Ok(());
+    }
+
     // Part 1
     let result = updates
         .iter()
@@ -92,12 +542,126 @@ fn main() {
     println!("{result}");
 
     // Part 2
+    // Updates that can't be put in a single order are reported and left out of the sum.
     let result = updates
         .iter()
         .filter(|u| !update_is_correct(u, &rules))
-        .map(|u| sorted_update(u, &rules))
+        .filter_map(|u| match sorted_update(u, &rules) {
+            Ok(sorted) => Some(sorted),
+            Err(e) => {
+                eprintln!("{}: can't be corrected: {e}", join(u));
+                None
+            }
+        })
         .inspect(|u| assert!(update_is_correct(u, &rules)))
         .map(|u| u[u.len() / 2])
         .sum::<i32>();
     println!("{result}");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = "47|53
+97|13
+97|61
+97|47
+75|29
+61|13
+75|53
+29|13
+97|29
+53|29
+61|53
+97|53
+61|29
+47|13
+75|47
+97|75
+47|61
+75|61
+47|29
+75|13
+53|13";
+
+    #[test]
+    fn test_sorted_update() {
+        let rules = OrderingRules::from_string(RULES).unwrap();
+        assert_eq!(
+            sorted_update(&[75, 97, 47, 61, 53], &rules),
+            Ok(vec![97, 75, 47, 61, 53])
+        );
+        assert_eq!(
+            sorted_update(&[97, 13, 75, 29, 47], &rules),
+            Ok(vec![97, 75, 47, 29, 13])
+        );
+    }
+
+    #[test]
+    fn test_fewest_moves() {
+        let update = [61, 13, 29];
+        let sorted = [61, 29, 13];
+        let moves = fewest_moves(&update, &sorted);
+        assert_eq!(
+            moves,
+            vec![Move {
+                page: 13,
+                after: Some(29)
+            }]
+        );
+
+        let update = [5, 1, 2, 3, 4, 0];
+        let sorted = [0, 1, 2, 3, 4, 5];
+        let moves = fewest_moves(&update, &sorted);
+        assert_eq!(
+            moves,
+            vec![
+                Move {
+                    page: 0,
+                    after: None
+                },
+                Move {
+                    page: 5,
+                    after: Some(4)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partial_rules() {
+        let rules = OrderingRules::from_string("2|3\n1|5").unwrap();
+        assert!(!update_is_correct(&[3, 1, 2], &rules));
+        assert!(update_is_correct(&[2, 1, 3], &rules));
+    }
+
+    #[test]
+    fn test_cycle() {
+        let rules = OrderingRules::from_string("1|2\n2|3\n3|4\n4|2").unwrap();
+        assert_eq!(
+            sorted_update(&[4, 3, 2, 1], &rules),
+            Err(OrderError::Cycle(vec![2, 3, 4]))
+        );
+    }
+
+    #[test]
+    fn test_cycle_after_ambiguity() {
+        let rules = OrderingRules::from_string("1|2\n2|1").unwrap();
+        assert_eq!(
+            sorted_update(&[3, 4, 1, 2], &rules),
+            Err(OrderError::Cycle(vec![2, 1]))
+        );
+    }
+
+    #[test]
+    fn test_ambiguous() {
+        let rules = OrderingRules::from_string("1|2\n1|3").unwrap();
+        assert_eq!(
+            sorted_update(&[3, 2, 1], &rules),
+            Err(OrderError::Ambiguous(vec![3, 2]))
+        );
+    }
 }
@@ -1,10 +1,11 @@
 use core::panic;
+use serde::Serialize;
 use std::{
-    collections::{HashMap, HashSet},
-    fs,
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fmt, fs,
 };
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 enum Direction {
     Up,
     Down,
@@ -13,6 +14,13 @@ enum Direction {
 }
 
 impl Direction {
+    const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+
     fn rotated(&self) -> Self {
         match self {
             Direction::Up => Direction::Right,
@@ -21,6 +29,43 @@ impl Direction {
             Direction::Left => Direction::Up,
         }
     }
+
+    fn rotated_left(&self) -> Self {
+        match self {
+            Direction::Up => Direction::Left,
+            Direction::Left => Direction::Down,
+            Direction::Down => Direction::Right,
+            Direction::Right => Direction::Up,
+        }
+    }
+
+    fn delta(&self) -> (i32, i32) {
+        match self {
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+        }
+    }
+
+    /// The edge of the board a guard heading this way walks off through.
+    fn edge(&self) -> &'static str {
+        match self {
+            Direction::Up => "top",
+            Direction::Down => "bottom",
+            Direction::Left => "left",
+            Direction::Right => "right",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -28,124 +73,667 @@ enum Entity {
     Empty,
     Guard(Direction),
     Obstacle,
+    /// Can only be walked onto while heading in its direction, and blocks the guard otherwise.
+    OneWay(Direction),
+    /// Turns the guard to its left as it walks onto it.
+    RotateLeft,
+    /// Sends the guard to the tile at `partner` as it walks onto it, still facing the same way.
+    Teleport {
+        label: char,
+        partner: usize,
+    },
     OutOfBounds,
 }
 
+impl Entity {
+    /// Whether walking onto the tile does nothing more than move the guard there.
+    fn is_plain(&self) -> bool {
+        matches!(self, Entity::Empty | Entity::Guard(_))
+    }
+
+    fn symbol(&self) -> char {
+        match self {
+            Entity::Empty | Entity::OutOfBounds => '.',
+            Entity::Guard(Direction::Up) => '^',
+            Entity::Guard(Direction::Down) => 'v',
+            Entity::Guard(Direction::Left) => '<',
+            Entity::Guard(Direction::Right) => '>',
+            Entity::Obstacle => '#',
+            Entity::OneWay(Direction::Up) => 'N',
+            Entity::OneWay(Direction::Down) => 'S',
+            Entity::OneWay(Direction::Left) => 'W',
+            Entity::OneWay(Direction::Right) => 'E',
+            Entity::RotateLeft => '%',
+            Entity::Teleport { label, .. } => *label,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Step {
+    pos: (i32, i32),
+    dir: Direction,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case", tag = "reason")]
+enum Exit {
+    LeftMap,
+    /// The guard walks the steps from `start` onwards forever, repeating every `period` steps.
+    Loop {
+        start: usize,
+        period: usize,
+    },
+    /// The guard walked into the guard at index `guard` on step `step`, stopping them both.
+    Collision {
+        step: usize,
+        guard: usize,
+    },
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exit::LeftMap => write!(f, "left the map"),
+            Exit::Loop { start, period } => {
+                write!(f, "loop from step {start} with period {period}")
+            }
+            Exit::Collision { step, guard } => {
+                write!(f, "collided with guard {guard} on step {step}")
+            }
+        }
+    }
+}
+
+/// The guard's full route, one step per tile moved or turn made.
+#[derive(Serialize)]
+struct Patrol {
+    steps: Vec<Step>,
+    /// Indices of the steps where the guard is facing a new direction.
+    turns: Vec<usize>,
+    exit: Exit,
+}
+
+impl Patrol {
+    fn new(steps: Vec<Step>, exit: Exit) -> Self {
+        let turns = (1..steps.len())
+            .filter(|&i| steps[i].dir != steps[i - 1].dir)
+            .collect();
+
+        Self { steps, turns, exit }
+    }
+
+    /// Returns where the patrol starts repeating itself forever and how often, or `None` if the
+    /// guard leaves the board. A guard stopped by a collision repeats its last step.
+    fn cycle(&self) -> Option<(usize, usize)> {
+        match self.exit {
+            Exit::LeftMap => None,
+            Exit::Loop { start, period } => Some((start, period)),
+            Exit::Collision { .. } => Some((self.steps.len() - 1, 1)),
+        }
+    }
+
+    /// Returns the guard's step at time `t`, or `None` once it has left the board.
+    fn state_at(&self, t: usize) -> Option<&Step> {
+        if t < self.steps.len() {
+            return Some(&self.steps[t]);
+        }
+
+        self.cycle()
+            .map(|(start, period)| &self.steps[start + (t - start) % period])
+    }
+
+    /// Cuts the patrol off after step `last`, unrolling its loop if it has one.
+    fn truncated(&self, last: usize, exit: Exit) -> Self {
+        let steps = (0..=last)
+            .map(|t| self.state_at(t).unwrap().clone())
+            .collect();
+
+        Self::new(steps, exit)
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("exit: {}\n", self.exit);
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let turn = if self.turns.contains(&i) { " turn" } else { "" };
+            text.push_str(&format!(
+                "{i}: {},{} {:?}{turn}\n",
+                step.pos.0, step.pos.1, step.dir
+            ));
+        }
+
+        text
+    }
+}
+
 struct Board {
-    entities: HashMap<(i32, i32), Entity>,
+    /// Every tile in row-major order.
+    tiles: Vec<Entity>,
     width: i32,
     height: i32,
+    /// For each tile and direction, the last tile the guard reaches walking straight from it
+    /// before an obstacle or special tile stops it, or `None` if it walks off the board instead.
+    stops: Vec<[Option<usize>; 4]>,
+}
+
+/// Which empty tiles are tried when looking for somewhere to put a new obstruction.
+#[derive(Clone, Copy, PartialEq)]
+enum Candidates {
+    /// Only the tiles the guard walks onto, as only those can change its route.
+    Path,
+    /// Every empty tile on the board.
+    Anywhere,
+}
+
+/// One straight walk between stops, followed by whatever stopped it.
+struct Segment {
+    start: (i32, i32),
+    stop: (i32, i32),
+    dir: Direction,
+    /// Where the guard is and which way it faces after the stop.
+    next: ((i32, i32), Direction),
+}
+
+#[derive(Serialize)]
+struct LoopShape {
+    /// Steps before the guard is back where it was, counting turns in place as steps.
+    length: usize,
+    /// The top left and bottom right corners of the box around every tile in the loop.
+    min: (i32, i32),
+    max: (i32, i32),
+    /// The tiles where the guard turns, in the order it reaches them.
+    turning_points: Vec<(i32, i32)>,
+}
+
+impl LoopShape {
+    fn from_segments(segments: &[Segment]) -> Self {
+        let tiles = || segments.iter().flat_map(|s| [s.start, s.stop]);
+
+        Self {
+            length: segments
+                .iter()
+                .map(|s| s.start.0.abs_diff(s.stop.0) + s.start.1.abs_diff(s.stop.1) + 1)
+                .sum::<u32>() as usize,
+            min: (
+                tiles().map(|t| t.0).min().unwrap(),
+                tiles().map(|t| t.1).min().unwrap(),
+            ),
+            max: (
+                tiles().map(|t| t.0).max().unwrap(),
+                tiles().map(|t| t.1).max().unwrap(),
+            ),
+            turning_points: segments
+                .iter()
+                .filter(|s| s.next.1 != s.dir)
+                .map(|s| s.next.0)
+                .collect(),
+        }
+    }
+}
+
+/// What becomes of the guard once a new obstruction is in place.
+enum Outcome {
+    Loop(LoopShape),
+    /// The guard walks off the board heading this way.
+    LeftMap(Direction),
+}
+
+#[derive(Serialize)]
+struct Trap {
+    obstruction: (i32, i32),
+    shape: LoopShape,
+}
+
+#[derive(Serialize)]
+struct Placements {
+    traps: Vec<Trap>,
+    /// How many of the placements that don't trap the guard let it leave through each edge.
+    exits: BTreeMap<&'static str, usize>,
+}
+
+impl Placements {
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for trap in &self.traps {
+            let (x, y) = trap.obstruction;
+            let shape = &trap.shape;
+            let turning_points: Vec<String> = shape
+                .turning_points
+                .iter()
+                .map(|(x, y)| format!("{x},{y}"))
+                .collect();
+            text += &format!(
+                "{x},{y}: loop of {} steps within {},{}-{},{} turning at {}\n",
+                shape.length,
+                shape.min.0,
+                shape.min.1,
+                shape.max.0,
+                shape.max.1,
+                turning_points.join(" ")
+            );
+        }
+
+        let exits: Vec<String> = Direction::ALL
+            .iter()
+            .map(|dir| format!("{} {}", dir.edge(), self.exits[dir.edge()]))
+            .collect();
+        text += &format!("exits: {}\n", exits.join(", "));
+
+        text
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Returns the first step on which the two guards are on the same tile, or are about to swap
+/// tiles with each other, in which case they stop before passing through each other.
+fn first_collision(a: &Patrol, b: &Patrol) -> Option<usize> {
+    // Once both guards are repeating themselves, their positions repeat together after the
+    // least common multiple of their periods, so there's no need to look any further than that.
+    let horizon = match (a.cycle(), b.cycle()) {
+        (Some((a_start, a_period)), Some((b_start, b_period))) => {
+            a_start.max(b_start) + a_period / gcd(a_period, b_period) * b_period
+        }
+        // A guard that never leaves can still be run into by the other one up until that one
+        // is gone.
+        (Some(_), None) => b.steps.len(),
+        (None, Some(_)) => a.steps.len(),
+        (None, None) => a.steps.len().min(b.steps.len()),
+    };
+
+    (0..horizon).find_map(|t| {
+        let (a_now, b_now) = (a.state_at(t)?.pos, b.state_at(t)?.pos);
+        if a_now == b_now {
+            return Some(t);
+        }
+
+        let (a_next, b_next) = (a.state_at(t + 1)?.pos, b.state_at(t + 1)?.pos);
+        (a_next == b_now && b_next == a_now).then_some(t)
+    })
 }
 
 impl Board {
     fn from_string(string: &str) -> Self {
-        let mut entities: HashMap<(i32, i32), Entity> = HashMap::new();
+        let width = string.lines().map(|l| l.len()).max().unwrap() as i32;
+        let height = string.lines().count() as i32;
+
+        let mut tiles = vec![Entity::Empty; (width * height) as usize];
+        let mut teleports: HashMap<char, Vec<usize>> = HashMap::new();
         for (y, line) in string.lines().enumerate() {
             for (x, char) in line.chars().enumerate() {
-                let (x, y) = (x as i32, y as i32);
-                let entity = match char {
-                    '#' => Some(Entity::Obstacle),
-                    '^' => Some(Entity::Guard(Direction::Up)),
-                    'v' => Some(Entity::Guard(Direction::Down)),
-                    '<' => Some(Entity::Guard(Direction::Left)),
-                    '>' => Some(Entity::Guard(Direction::Right)),
-                    _ => None,
+                let index = y * width as usize + x;
+                tiles[index] = match char {
+                    '#' => Entity::Obstacle,
+                    '^' => Entity::Guard(Direction::Up),
+                    'v' => Entity::Guard(Direction::Down),
+                    '<' => Entity::Guard(Direction::Left),
+                    '>' => Entity::Guard(Direction::Right),
+                    'N' => Entity::OneWay(Direction::Up),
+                    'S' => Entity::OneWay(Direction::Down),
+                    'W' => Entity::OneWay(Direction::Left),
+                    'E' => Entity::OneWay(Direction::Right),
+                    '%' => Entity::RotateLeft,
+                    '0'..='9' => {
+                        teleports.entry(char).or_default().push(index);
+                        continue;
+                    }
+                    _ => Entity::Empty,
                 };
-                if let Some(entity) = entity {
-                    entities.insert((x, y), entity);
-                }
             }
         }
 
-        let width = string.lines().map(|l| l.len()).max().unwrap() as i32;
-        let height = string.lines().count() as i32;
+        for (label, ends) in teleports {
+            let [a, b] = ends[..] else {
+                panic!("Teleport {label} needs exactly two ends!");
+            };
+            tiles[a] = Entity::Teleport { label, partner: b };
+            tiles[b] = Entity::Teleport { label, partner: a };
+        }
 
-        Self {
-            entities,
+        let mut board = Self {
+            tiles,
             width,
             height,
+            stops: Vec::new(),
+        };
+        board.stops = board.compute_stops();
+
+        board
+    }
+
+    fn compute_stops(&self) -> Vec<[Option<usize>; 4]> {
+        let mut stops = vec![[None; 4]; self.tiles.len()];
+        for dir in Direction::ALL {
+            let (dx, dy) = dir.delta();
+
+            // Visit the tiles furthest along the direction first, so the stop for the tile ahead
+            // is always known by the time it's needed.
+            let xs: Vec<i32> = if dx > 0 {
+                (0..self.width).rev().collect()
+            } else {
+                (0..self.width).collect()
+            };
+            let ys: Vec<i32> = if dy > 0 {
+                (0..self.height).rev().collect()
+            } else {
+                (0..self.height).collect()
+            };
+
+            for &y in ys.iter() {
+                for &x in xs.iter() {
+                    let index = self.index(&(x, y)).unwrap();
+                    stops[index][dir.index()] = match self.index(&(x + dx, y + dy)) {
+                        None => None,
+                        Some(next) if !self.tiles[next].is_plain() => Some(index),
+                        Some(next) => stops[next][dir.index()],
+                    };
+                }
+            }
         }
+
+        stops
+    }
+
+    fn index(&self, pos: &(i32, i32)) -> Option<usize> {
+        ((0..self.width).contains(&pos.0) && (0..self.height).contains(&pos.1))
+            .then(|| (pos.1 * self.width + pos.0) as usize)
+    }
+
+    fn position(&self, index: usize) -> (i32, i32) {
+        (index as i32 % self.width, index as i32 / self.width)
     }
 
     fn at(&self, pos: &(i32, i32)) -> Entity {
-        if !(0..self.width).contains(&pos.0) || !(0..self.height).contains(&pos.1) {
-            return Entity::OutOfBounds;
+        match self.index(pos) {
+            Some(index) => self.tiles[index].clone(),
+            None => Entity::OutOfBounds,
         }
+    }
 
-        self.entities.get(pos).cloned().unwrap_or(Entity::Empty)
+    fn guards(&self) -> Vec<((i32, i32), Direction)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entity)| match entity {
+                Entity::Guard(dir) => Some((self.position(index), dir.clone())),
+                _ => None,
+            })
+            .collect()
     }
 
+    /// Returns the only guard, for the parts of the puzzle that only make sense with one.
     fn guard(&self) -> ((i32, i32), Direction) {
-        for (pos, entity) in self.entities.iter() {
-            if let Entity::Guard(dir) = entity {
-                return (*pos, dir.clone());
-            }
+        match &self.guards()[..] {
+            [guard] => guard.clone(),
+            guards => panic!(
+                "Expected one guard, found {}! Try `guards` or `patrol --guard <index>` instead.",
+                guards.len()
+            ),
         }
-
-        panic!("No guard found!");
     }
 
     fn walk(&self, pos: &(i32, i32), dir: &Direction) -> ((i32, i32), Direction) {
-        let next_pos = match dir {
-            Direction::Up => (pos.0, pos.1 - 1),
-            Direction::Down => (pos.0, pos.1 + 1),
-            Direction::Left => (pos.0 - 1, pos.1),
-            Direction::Right => (pos.0 + 1, pos.1),
-        };
+        let (dx, dy) = dir.delta();
+        let next_pos = (pos.0 + dx, pos.1 + dy);
 
         match self.at(&next_pos) {
             Entity::Obstacle => (*pos, dir.rotated()),
+            Entity::OneWay(allowed) if allowed != *dir => (*pos, dir.rotated()),
+            Entity::RotateLeft => (next_pos, dir.rotated_left()),
+            Entity::Teleport { partner, .. } => (self.position(partner), dir.clone()),
             _ => (next_pos, dir.clone()),
         }
     }
 
-    fn get_visited(&self) -> HashSet<(i32, i32)> {
-        let mut visited: HashSet<(i32, i32)> = HashSet::new();
-        let (mut pos, mut dir) = self.guard();
-        while !matches!(self.at(&pos), Entity::OutOfBounds) {
-            visited.insert(pos);
+    /// Returns the tile the guard stops at walking straight from `pos`, as if there were an
+    /// extra obstacle at `new_obstacle`, or `None` if it walks off the board.
+    fn stop_with_obstacle(
+        &self,
+        pos: &(i32, i32),
+        dir: &Direction,
+        new_obstacle: &(i32, i32),
+    ) -> Option<(i32, i32)> {
+        let (dx, dy) = dir.delta();
+        let stop = self.stops[self.index(pos).unwrap()][dir.index()].map(|i| self.position(i));
+
+        // How far ahead the new obstacle is, if it's in the guard's way at all.
+        let (ox, oy) = (new_obstacle.0 - pos.0, new_obstacle.1 - pos.1);
+        let obstacle_distance = (ox * dy == oy * dx)
+            .then_some(ox * dx + oy * dy)
+            .filter(|d| *d > 0);
+        let stop_distance = stop.map(|s| (s.0 - pos.0) * dx + (s.1 - pos.1) * dy);
+
+        match (obstacle_distance, stop_distance) {
+            (Some(o), None) => Some((pos.0 + dx * (o - 1), pos.1 + dy * (o - 1))),
+            (Some(o), Some(s)) if o <= s => Some((pos.0 + dx * (o - 1), pos.1 + dy * (o - 1))),
+            _ => stop,
+        }
+    }
+
+    fn patrol(&self) -> Patrol {
+        let (pos, dir) = self.guard();
+        self.patrol_from(pos, dir)
+    }
+
+    /// Follows a guard one step at a time until it leaves the board or repeats itself.
+    fn patrol_from(&self, mut pos: (i32, i32), mut dir: Direction) -> Patrol {
+        let mut steps: Vec<Step> = Vec::new();
+        let mut first_seen: HashMap<((i32, i32), usize), usize> = HashMap::new();
+
+        let exit = loop {
+            if matches!(self.at(&pos), Entity::OutOfBounds) {
+                break Exit::LeftMap;
+            }
+
+            if let Some(&start) = first_seen.get(&(pos, dir.index())) {
+                break Exit::Loop {
+                    start,
+                    period: steps.len() - start,
+                };
+            }
+            first_seen.insert((pos, dir.index()), steps.len());
+
+            steps.push(Step {
+                pos,
+                dir: dir.clone(),
+            });
             (pos, dir) = self.walk(&pos, &dir);
+        };
+
+        Patrol::new(steps, exit)
+    }
+
+    /// Moves every guard in lockstep, stopping any two that collide. A stopped guard stays on
+    /// its tile, where the others can still walk into it.
+    ///
+    /// Guards don't block each other, so each one's route is worked out alone first. Then the
+    /// earliest collisions between any pair are applied, stopping the guards involved, and the
+    /// collisions are looked for again until there are none left.
+    fn patrols(&self) -> Vec<Patrol> {
+        let mut patrols: Vec<Patrol> = self
+            .guards()
+            .into_iter()
+            .map(|(pos, dir)| self.patrol_from(pos, dir))
+            .collect();
+        let stopped = |patrol: &Patrol| matches!(patrol.exit, Exit::Collision { .. });
+
+        loop {
+            let mut collisions: Vec<(usize, usize, usize)> = Vec::new();
+            for i in 0..patrols.len() {
+                for j in (i + 1)..patrols.len() {
+                    if stopped(&patrols[i]) && stopped(&patrols[j]) {
+                        continue;
+                    }
+                    if let Some(step) = first_collision(&patrols[i], &patrols[j]) {
+                        collisions.push((step, i, j));
+                    }
+                }
+            }
+            collisions.sort();
+
+            let Some(&(first, _, _)) = collisions.first() else {
+                break;
+            };
+
+            // A guard stopped on a step can still be part of another collision on that same
+            // step, though it keeps the first guard it collided with.
+            for &(step, i, j) in collisions.iter().take_while(|(step, ..)| *step == first) {
+                for (guard, other) in [(i, j), (j, i)] {
+                    if !stopped(&patrols[guard]) {
+                        let exit = Exit::Collision { step, guard: other };
+                        patrols[guard] = patrols[guard].truncated(step, exit);
+                    }
+                }
+            }
+        }
+
+        patrols
+    }
+
+    fn get_visited(&self) -> HashSet<(i32, i32)> {
+        self.patrol().steps.iter().map(|step| step.pos).collect()
+    }
+
+    /// Draws the patrol over the board, with `|` and `-` for the guard's path and `+` where it
+    /// turns or crosses its own path.
+    fn render_patrol(&self, patrol: &Patrol) -> String {
+        let mut cells: Vec<char> = self
+            .tiles
+            .iter()
+            .map(|tile| match tile {
+                Entity::Guard(_) => '.',
+                tile => tile.symbol(),
+            })
+            .collect();
+
+        let turns: HashSet<usize> = patrol.turns.iter().copied().collect();
+        for (i, step) in patrol.steps.iter().enumerate() {
+            let index = self.index(&step.pos).unwrap();
+            if !self.tiles[index].is_plain() {
+                continue;
+            }
+            let path = match step.dir {
+                Direction::Up | Direction::Down => '|',
+                Direction::Left | Direction::Right => '-',
+            };
+            cells[index] = match cells[index] {
+                '.' if !turns.contains(&(i + 1)) => path,
+                c if c == path => path,
+                _ => '+',
+            };
+        }
+
+        if let Some(start) = patrol.steps.first() {
+            cells[self.index(&start.pos).unwrap()] = Entity::Guard(start.dir.clone()).symbol();
         }
 
-        visited
+        cells
+            .chunks(self.width as usize)
+            .map(|row| row.iter().collect::<String>() + "\n")
+            .collect()
     }
 
-    fn would_enter_into_loop(
+    /// Follows the guard by jumping from stop to stop with the stop table, treating
+    /// `new_obstacle` as an obstacle without changing the board.
+    fn outcome_with_obstacle(
         &self,
         pos: &(i32, i32),
         dir: &Direction,
         new_obstacle: &(i32, i32),
-    ) -> bool {
+    ) -> Outcome {
         assert_ne!(pos, new_obstacle);
 
-        let mut entities = self.entities.clone();
-        entities.insert(*new_obstacle, Entity::Obstacle);
-        let board_with_obstacle = Board {
-            entities,
-            width: self.width,
-            height: self.height,
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut first_seen: HashMap<((i32, i32), usize), usize> = HashMap::new();
+        let (mut pos, mut dir) = (*pos, dir.clone());
+        while let Some(stop) = self.stop_with_obstacle(&pos, &dir, new_obstacle) {
+            let (dx, dy) = dir.delta();
+            let next = if (stop.0 + dx, stop.1 + dy) == *new_obstacle {
+                (stop, dir.rotated())
+            } else {
+                self.walk(&stop, &dir)
+            };
+
+            // The segments after the first one to reach this stop bring the guard back to it.
+            if let Some(&first) = first_seen.get(&(stop, dir.index())) {
+                segments.push(Segment {
+                    start: pos,
+                    stop,
+                    dir,
+                    next,
+                });
+                return Outcome::Loop(LoopShape::from_segments(&segments[first + 1..]));
+            }
+            first_seen.insert((stop, dir.index()), segments.len());
+
+            segments.push(Segment {
+                start: pos,
+                stop,
+                dir: dir.clone(),
+                next: next.clone(),
+            });
+            (pos, dir) = next;
+        }
+
+        Outcome::LeftMap(dir)
+    }
+
+    /// Tries a new obstruction on each candidate tile, collecting the ones that trap the guard
+    /// in a loop and counting where it leaves the board for the rest.
+    fn obstruction_placements(&self, candidates: Candidates) -> Placements {
+        let mut placements = Placements {
+            traps: Vec::new(),
+            exits: Direction::ALL.iter().map(|dir| (dir.edge(), 0)).collect(),
+        };
+        let mut place = |obstruction: (i32, i32), outcome: Outcome| match outcome {
+            Outcome::Loop(shape) => placements.traps.push(Trap { obstruction, shape }),
+            Outcome::LeftMap(dir) => *placements.exits.get_mut(dir.edge()).unwrap() += 1,
         };
 
-        let (mut slow_pos, mut slow_dir) = (*pos, dir.clone());
-        let (mut fast_pos, mut fast_dir) = (*pos, dir.clone());
-        while !matches!(board_with_obstacle.at(&fast_pos), Entity::OutOfBounds) {
-            (slow_pos, slow_dir) = board_with_obstacle.walk(&slow_pos, &slow_dir);
-            (fast_pos, fast_dir) = board_with_obstacle.walk(&fast_pos, &fast_dir);
-            (fast_pos, fast_dir) = board_with_obstacle.walk(&fast_pos, &fast_dir);
-            if fast_dir == slow_dir && fast_pos == slow_pos {
-                return true;
+        match candidates {
+            Candidates::Path => {
+                // An obstruction only changes the guard's route from the first time it would
+                // have walked into it, so each one is tested from the guard's position just
+                // before then. Only empty tiles can take one, which also rules out the guard's
+                // starting tile. The patrol ends even if the guard is already stuck in a loop.
+                let mut tested: HashSet<(i32, i32)> = HashSet::new();
+                for Step { pos, dir } in self.patrol().steps {
+                    let (dx, dy) = dir.delta();
+                    let ahead = (pos.0 + dx, pos.1 + dy);
+                    if self.at(&ahead) == Entity::Empty && tested.insert(ahead) {
+                        place(ahead, self.outcome_with_obstacle(&pos, &dir, &ahead));
+                    }
+                }
+            }
+            Candidates::Anywhere => {
+                let (guard_pos, guard_dir) = self.guard();
+                for (index, tile) in self.tiles.iter().enumerate() {
+                    if *tile == Entity::Empty {
+                        let obstruction = self.position(index);
+                        place(
+                            obstruction,
+                            self.outcome_with_obstacle(&guard_pos, &guard_dir, &obstruction),
+                        );
+                    }
+                }
             }
         }
 
-        false
+        placements
     }
 
     fn stuck_in_loop_obstruction_positions(&self) -> HashSet<(i32, i32)> {
-        let (guard_pos, guard_dir) = self.guard();
-
-        self.get_visited()
+        self.obstruction_placements(Candidates::Path)
+            .traps
             .into_iter()
-            .filter(|v| *v != guard_pos && self.would_enter_into_loop(&guard_pos, &guard_dir, v))
+            .map(|trap| trap.obstruction)
             .collect()
     }
 }
@@ -154,6 +742,69 @@ fn main() {
     let input = fs::read_to_string("input/input.txt").unwrap();
     let board = Board::from_string(&input);
 
+    // Usage: day_6 [patrol [--format text|json|board] [--guard <index>] | guards
+    //              | traps [--anywhere] [--json]]
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("traps") {
+        let candidates = if args.iter().any(|a| a == "--anywhere") {
+            Candidates::Anywhere
+        } else {
+            Candidates::Path
+        };
+
+        let placements = board.obstruction_placements(candidates);
+        if args.iter().any(|a| a == "--json") {
+            println!("{}", serde_json::to_string_pretty(&placements).unwrap());
+        } else {
+            print!("{}", placements.to_text());
+        }
+        return;
+    }
+
+    if args.first().map(String::as_str) == Some("guards") {
+        for (i, patrol) in board.patrols().iter().enumerate() {
+            let start = &patrol.steps[0];
+            println!(
+                "guard {i} from {},{} facing {:?}: {} after {} steps",
+                start.pos.0,
+                start.pos.1,
+                start.dir,
+                patrol.exit,
+                patrol.steps.len()
+            );
+        }
+        return;
+    }
+
+    if args.first().map(String::as_str) == Some("patrol") {
+        let option = |name: &str| {
+            args.iter()
+                .position(|a| a == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        let format = option("--format").map_or("text", String::as_str);
+
+        // Picking one guard out of several shows its patrol as it plays out alongside the others.
+        let patrol = match option("--guard") {
+            Some(guard) => {
+                let guard: usize = guard.parse().unwrap();
+                board
+                    .patrols()
+                    .into_iter()
+                    .nth(guard)
+                    .unwrap_or_else(|| panic!("No guard {guard}!"))
+            }
+            None => board.patrol(),
+        };
+        match format {
+            "text" => print!("{}", patrol.to_text()),
+            "json" => println!("{}", serde_json::to_string_pretty(&patrol).unwrap()),
+            "board" => print!("{}", board.render_patrol(&patrol)),
+            _ => panic!("Unknown format {format}"),
+        }
+        return;
+    }
+
     let result = board.get_visited().len();
     println!("{result}");
 
@@ -181,4 +832,91 @@ mod tests {
         let board = Board::from_string(input);
         assert_eq!(board.stuck_in_loop_obstruction_positions().len(), 6);
     }
+
+    #[test]
+    fn test_placements_around_looping_guard() {
+        let board = Board::from_string(".#...\n....#\n.^...\n#....\n...#.");
+        assert!(matches!(
+            board.patrol().exit,
+            Exit::Loop {
+                start: 0,
+                period: 12
+            }
+        ));
+
+        // Blocking the guard's loop anywhere lets it out, and it keeps looping around anything
+        // placed off its path.
+        let path = board.obstruction_placements(Candidates::Path);
+        assert!(path.traps.is_empty());
+        assert_eq!(path.exits.values().sum::<usize>(), 7);
+
+        let anywhere = board.obstruction_placements(Candidates::Anywhere);
+        assert_eq!(anywhere.traps.len(), 13);
+        assert_eq!(anywhere.exits, path.exits);
+    }
+
+    #[test]
+    fn test_head_on_collision() {
+        let board = Board::from_string("..........\n.>......<.\n..........");
+        let patrols = board.patrols();
+
+        // The guards would swap tiles between steps 3 and 4, so they stop face to face on step 3.
+        assert_eq!(patrols[0].steps.last().unwrap().pos, (4, 1));
+        assert_eq!(patrols[1].steps.last().unwrap().pos, (5, 1));
+        assert!(matches!(
+            patrols[0].exit,
+            Exit::Collision { step: 3, guard: 1 }
+        ));
+        assert!(matches!(
+            patrols[1].exit,
+            Exit::Collision { step: 3, guard: 0 }
+        ));
+    }
+
+    #[test]
+    fn test_collision_with_stopped_guards() {
+        let board = Board::from_string(">.<\n...\n...\n.^.");
+        let patrols = board.patrols();
+
+        for (patrol, other) in patrols.iter().zip([1, 0]) {
+            assert_eq!(patrol.steps.last().unwrap().pos, (1, 0));
+            assert!(matches!(
+                patrol.exit,
+                Exit::Collision { step: 1, guard } if guard == other
+            ));
+        }
+
+        // The first two guards are still standing where they met when the third one gets there.
+        assert_eq!(patrols[2].steps.last().unwrap().pos, (1, 0));
+        assert!(matches!(
+            patrols[2].exit,
+            Exit::Collision { step: 3, guard: 0 }
+        ));
+    }
+
+    #[test]
+    fn test_collision_with_looping_guard() {
+        let mut rows = vec![vec!['.'; 12]; 45];
+        for (x, y) in [(9, 2), (8, 7), (1, 6), (2, 1)] {
+            rows[y][x] = '#';
+        }
+        rows[2][3] = '>';
+        rows[44][5] = '^';
+        let input: Vec<String> = rows.iter().map(|row| row.iter().collect()).collect();
+        let board = Board::from_string(&input.join("\n"));
+
+        assert!(matches!(
+            board.patrol_from((3, 2), Direction::Right).exit,
+            Exit::Loop { period: 24, .. }
+        ));
+
+        let patrols = board.patrols();
+        for (patrol, other) in patrols.iter().zip([1, 0]) {
+            assert_eq!(patrol.steps.last().unwrap().pos, (5, 6));
+            assert!(matches!(
+                patrol.exit,
+                Exit::Collision { step: 38, guard } if guard == other
+            ));
+        }
+    }
 }
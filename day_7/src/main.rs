@@ -1,92 +1,478 @@
-use std::fs;
+use num_bigint::BigInt;
+use num_integer::Roots;
+use num_traits::{
+    checked_pow, CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Signed, ToPrimitive,
+};
+use std::{env, fmt, fs, str::FromStr};
 
-enum Op {
-    Add,
-    Mult,
-    Concat,
+/// An integer type the solver can work in. Arithmetic that doesn't fit is a dead end rather
+/// than wrapping, so every type finds the same solutions wherever the numbers fit.
+trait Number:
+    Clone
+    + fmt::Debug
+    + fmt::Display
+    + Ord
+    + FromStr
+    + From<u8>
+    + Into<BigInt>
+    + Signed
+    + Roots
+    + CheckedAdd
+    + CheckedSub
+    + CheckedMul
+    + CheckedDiv
+    + ToPrimitive
+{
 }
 
-fn concat(a: &i64, b: &i64) -> i64 {
-    let mut pow = 10;
+impl<T> Number for T where
+    T: Clone
+        + fmt::Debug
+        + fmt::Display
+        + Ord
+        + FromStr
+        + From<u8>
+        + Into<BigInt>
+        + Signed
+        + Roots
+        + CheckedAdd
+        + CheckedSub
+        + CheckedMul
+        + CheckedDiv
+        + ToPrimitive
+{
+}
+
+/// An operator the solver can put between two numbers.
+struct OpSpec<N> {
+    symbol: &'static str,
+    /// Returns `None` when the operator can't be applied to the pair, e.g. division with a
+    /// remainder, or when the result doesn't fit.
+    apply: fn(&N, &N) -> Option<N>,
+    /// Returns the totals that `apply` turns into the first number given the second, for
+    /// solving from the right. There can be two, as an even power hides the sign of its base.
+    unapply: fn(&N, &N) -> [Option<N>; 2],
+    /// Whether the operator never makes a non-negative running total smaller when the next
+    /// number is positive. The solver only gives up on totals above the target when every
+    /// allowed operator does this.
+    grows: bool,
+}
+
+/// The largest power the solver raises to, so arbitrary precision powers stay a sensible size.
+/// Any base other than 0 and ±1 overflows `i128` well before this.
+const MAX_EXPONENT: u32 = 1024;
+
+fn operators<N: Number>() -> [OpSpec<N>; 6] {
+    [
+        OpSpec {
+            symbol: "+",
+            apply: |a, b| a.checked_add(b),
+            unapply: |r, b| [r.checked_sub(b), None],
+            grows: true,
+        },
+        OpSpec {
+            symbol: "*",
+            apply: |a, b| a.checked_mul(b),
+            unapply: |r, b| [exact_div(r, b), None],
+            grows: true,
+        },
+        OpSpec {
+            symbol: "||",
+            apply: concat,
+            unapply: |r, b| [unconcat(r, b), None],
+            grows: true,
+        },
+        OpSpec {
+            symbol: "-",
+            apply: |a, b| a.checked_sub(b),
+            unapply: |r, b| [r.checked_add(b), None],
+            grows: false,
+        },
+        OpSpec {
+            symbol: "/",
+            apply: exact_div,
+            unapply: |r, b| [r.checked_mul(b).filter(|_| !b.is_zero()), None],
+            grows: false,
+        },
+        OpSpec {
+            symbol: "^",
+            apply: |a, b| checked_pow(a.clone(), exponent(b)?),
+            unapply: root,
+            grows: true,
+        },
+    ]
+}
+
+const PART_1_OPS: &str = "+,*";
+const PART_2_OPS: &str = "+,*,||";
+
+/// Parses a comma separated list of operator symbols, e.g. `+,*,||`.
+fn parse_ops<N: Number>(s: &str) -> Result<Vec<OpSpec<N>>, String> {
+    s.split(',')
+        .map(|symbol| {
+            operators()
+                .into_iter()
+                .find(|op| op.symbol == symbol)
+                .ok_or_else(|| format!("Unknown operator {symbol}"))
+        })
+        .collect()
+}
+
+/// Divides `a` by `b`, if it leaves no remainder.
+fn exact_div<N: Number>(a: &N, b: &N) -> Option<N> {
+    let quotient = a.checked_div(b)?;
+    (a.clone() % b.clone()).is_zero().then_some(quotient)
+}
+
+/// The power of ten `concat` shifts its first number up by to make room for `b`.
+fn concat_shift<N: Number>(b: &N) -> Option<N> {
+    let ten = N::from(10);
+    let mut pow = ten.clone();
     while *b >= pow {
-        pow *= 10;
+        pow = pow.checked_mul(&ten)?;
     }
-    
-    a * pow + b
+
+    Some(pow)
+}
+
+fn concat<N: Number>(a: &N, b: &N) -> Option<N> {
+    a.checked_mul(&concat_shift(b)?)?.checked_add(b)
+}
+
+/// Strips `b` off the end of `r`, if `r` could be the concatenation of something with it.
+fn unconcat<N: Number>(r: &N, b: &N) -> Option<N> {
+    exact_div(&r.checked_sub(b)?, &concat_shift(b)?)
 }
 
-fn apply_op(op: &Op, a: &i64, b: &i64) -> i64 {
-    match op {
-        Op::Add => a + b,
-        Op::Mult => a * b,
-        Op::Concat => concat(a, b),
+fn exponent<N: Number>(b: &N) -> Option<usize> {
+    b.to_u32()
+        .filter(|e| *e <= MAX_EXPONENT)
+        .map(|e| e as usize)
+}
+
+/// Returns the whole numbers whose `n`th power is `r`.
+fn root<N: Number>(r: &N, n: &N) -> [Option<N>; 2] {
+    let roots = || {
+        let n = exponent(n).filter(|n| *n > 0)?;
+        let magnitude = if r.is_negative() {
+            N::zero().checked_sub(r)?
+        } else {
+            r.clone()
+        };
+        let base = magnitude.nth_root(n as u32);
+        if checked_pow(base.clone(), n)? != magnitude {
+            return None;
+        }
+
+        Some(match (r.is_negative(), n % 2 == 0) {
+            (true, true) => [None, None],
+            (true, false) => [Some(-base), None],
+            (false, true) if !base.is_zero() => [Some(base.clone()), Some(-base)],
+            (false, _) => [Some(base), None],
+        })
+    };
+
+    roots().unwrap_or([None, None])
+}
+
+/// Which end of the equation the search starts from.
+#[derive(Clone, Copy, PartialEq)]
+enum Solver {
+    /// Applies operators from the first number towards the result.
+    Forward,
+    /// Undoes operators from the result back towards the first number, which rules out most
+    /// branches straight away.
+    Inverse,
+}
+
+impl Solver {
+    fn from_string(s: &str) -> Self {
+        match s {
+            "forward" => Solver::Forward,
+            "inverse" => Solver::Inverse,
+            _ => panic!("Unknown solver {s}"),
+        }
     }
 }
 
 #[derive(Debug)]
-struct Equation {
-    nums: Vec<i64>,
-    result: i64,
+struct Equation<N> {
+    nums: Vec<N>,
+    result: N,
 }
 
-impl Equation {
-    fn from_str(s: &str) -> Self {
+impl<N: Number> Equation<N> {
+    fn from_str(s: &str) -> Result<Self, N::Err> {
         let (result, nums) = s.split_once(':').unwrap();
-        let result: i64 = result.parse().unwrap();
+        let result = result.parse()?;
         let nums = nums
             .split_whitespace()
-            .map(|n| n.parse().unwrap())
-            .collect();
+            .map(|n| n.parse())
+            .collect::<Result<_, _>>()?;
 
-        Self { nums, result }
+        Ok(Self { nums, result })
     }
-}
 
-fn has_solution_1(first: &i64, rest: &[i64], solution: &i64) -> bool {
-    if rest.is_empty() {
-        first == solution
-    } else if first > solution {
-        false
-    } else {
-        [Op::Add, Op::Mult]
-            .iter()
-            .any(|op| has_solution_1(&apply_op(op, first, &rest[0]), &rest[1..], solution))
+    /// Calls `found` with every sequence of `ops` that solves the equation, until it returns
+    /// `false`.
+    fn search<'a>(
+        &self,
+        ops: &'a [OpSpec<N>],
+        solver: Solver,
+        found: &mut dyn FnMut(&[&'a OpSpec<N>]) -> bool,
+    ) {
+        let Some((first, rest)) = self.nums.split_first() else {
+            return;
+        };
+
+        let mut search = Search {
+            ops,
+            target: self.result.clone(),
+            prune: ops.iter().all(|op| op.grows)
+                && !first.is_negative()
+                && rest.iter().all(Signed::is_positive),
+            found,
+        };
+        let mut chosen = Vec::with_capacity(rest.len());
+
+        // Multiplying by zero or raising to it forgets the total before, so it can't be undone
+        // and those equations are searched forwards instead.
+        if solver == Solver::Forward || rest.iter().any(|n| n.is_zero()) {
+            search.step(first, rest, &mut chosen);
+        } else {
+            search.step_back(&self.result, &self.nums, &mut chosen);
+        }
+    }
+
+    fn first_solution<'a>(
+        &self,
+        ops: &'a [OpSpec<N>],
+        solver: Solver,
+    ) -> Option<Vec<&'a OpSpec<N>>> {
+        let mut solution = None;
+        self.search(ops, solver, &mut |chosen| {
+            solution = Some(chosen.to_vec());
+            false
+        });
+
+        solution
+    }
+
+    fn solutions<'a>(&self, ops: &'a [OpSpec<N>], solver: Solver) -> Vec<Vec<&'a OpSpec<N>>> {
+        let mut solutions = Vec::new();
+        self.search(ops, solver, &mut |chosen| {
+            solutions.push(chosen.to_vec());
+            true
+        });
+
+        solutions
+    }
+
+    fn count_solutions(&self, ops: &[OpSpec<N>], solver: Solver) -> usize {
+        let mut count = 0;
+        self.search(ops, solver, &mut |_| {
+            count += 1;
+            true
+        });
+
+        count
+    }
+
+    /// Writes the numbers out with `ops` between them, e.g. `81 + 40 * 27`. The operators
+    /// are applied left to right, not by precedence.
+    fn expression(&self, ops: &[&OpSpec<N>]) -> String {
+        let mut expression = self.nums[0].to_string();
+        for (op, num) in ops.iter().zip(&self.nums[1..]) {
+            expression += &format!(" {} {num}", op.symbol);
+        }
+
+        expression
     }
 }
 
-fn has_solution_2(first: &i64, rest: &[i64], solution: &i64) -> bool {
-    if rest.is_empty() {
-        first == solution
-    } else if first > solution {
-        false
-    } else {
-        [Op::Add, Op::Mult, Op::Concat]
-            .iter()
-            .any(|op| has_solution_2(&apply_op(op, first, &rest[0]), &rest[1..], solution))
+struct Search<'a, 'f, N> {
+    ops: &'a [OpSpec<N>],
+    target: N,
+    /// Whether a running total above the target can be given up on, as no allowed operator
+    /// can bring it back down.
+    prune: bool,
+    found: &'f mut dyn FnMut(&[&'a OpSpec<N>]) -> bool,
+}
+
+impl<'a, N: Number> Search<'a, '_, N> {
+    /// Tries every operator between `first` and the next number, keeping the ones tried so far
+    /// in `chosen`. Returns `false` once `found` has asked to stop.
+    fn step(&mut self, first: &N, rest: &[N], chosen: &mut Vec<&'a OpSpec<N>>) -> bool {
+        if rest.is_empty() {
+            *first != self.target || (self.found)(chosen)
+        } else if self.prune && *first > self.target {
+            true
+        } else {
+            let ops = self.ops;
+            ops.iter().all(|op| {
+                let Some(next) = (op.apply)(first, &rest[0]) else {
+                    return true;
+                };
+                chosen.push(op);
+                let more = self.step(&next, &rest[1..], chosen);
+                chosen.pop();
+                more
+            })
+        }
     }
+
+    /// Undoes every operator that could have produced `total` from the last of `nums`, keeping
+    /// the ones tried so far in `chosen` from the right. Returns `false` once `found` has asked
+    /// to stop.
+    fn step_back(&mut self, total: &N, nums: &[N], chosen: &mut Vec<&'a OpSpec<N>>) -> bool {
+        let (last, init) = nums.split_last().unwrap();
+        if init.is_empty() {
+            return total != last
+                || (self.found)(&chosen.iter().rev().copied().collect::<Vec<_>>());
+        }
+
+        // When totals only grow, none of them can be below the first number.
+        if self.prune && *total < nums[0] {
+            return true;
+        }
+
+        let ops = self.ops;
+        ops.iter().all(|op| {
+            (op.unapply)(total, last)
+                .into_iter()
+                .flatten()
+                .all(|before| {
+                    chosen.push(op);
+                    let more = self.step_back(&before, init, chosen);
+                    chosen.pop();
+                    more
+                })
+        })
+    }
+}
+
+fn parse_equations<N: Number>(input: &str) -> Result<Vec<Equation<N>>, N::Err> {
+    input.lines().map(Equation::from_str).collect()
 }
 
 fn main() {
     let input = fs::read_to_string("input/input.txt").unwrap();
-    let eqs: Vec<Equation> = input.lines().map(Equation::from_str).collect();
 
-    let result: i64 = eqs
+    // Usage: day_7 [explain [--all | --count]] [--ops <symbol>,...] [--solver forward|inverse]
+    //              [--precision auto|i64|i128|big]
+    // e.g. --ops +,*,||,-,/,^
+    let args: Vec<String> = env::args().skip(1).collect();
+    let precision = args
         .iter()
-        .filter(|e| match e.nums.split_first() {
-            Some((first, rest)) => has_solution_1(first, rest, &e.result),
-            None => false,
-        })
-        .map(|e| e.result)
-        .sum();
-    println!("{result}");
+        .position(|a| a == "--precision")
+        .and_then(|i| args.get(i + 1))
+        .map_or("auto", String::as_str);
+
+    match precision {
+        "i64" => run::<i64>(&input, &args),
+        "i128" => run::<i128>(&input, &args),
+        "big" => run::<BigInt>(&input, &args),
+        // The narrowest type every number in the input fits in.
+        "auto" if parse_equations::<i64>(&input).is_ok() => run::<i64>(&input, &args),
+        "auto" if parse_equations::<i128>(&input).is_ok() => run::<i128>(&input, &args),
+        "auto" => run::<BigInt>(&input, &args),
+        _ => panic!("Unknown precision {precision}"),
+    }
+}
+
+fn run<N: Number>(input: &str, args: &[String])
+where
+    N::Err: fmt::Debug,
+{
+    let eqs: Vec<Equation<N>> = parse_equations(input).unwrap();
 
-    let result: i64 = eqs
+    let solver = args
         .iter()
-        .filter(|e| match e.nums.split_first() {
-            Some((first, rest)) => has_solution_2(first, rest, &e.result),
-            None => false,
-        })
-        .map(|e| e.result)
-        .sum();
-    println!("{result}");
+        .position(|a| a == "--solver")
+        .and_then(|i| args.get(i + 1))
+        .map_or(Solver::Inverse, |s| Solver::from_string(s));
+    let ops = args
+        .iter()
+        .position(|a| a == "--ops")
+        .and_then(|i| args.get(i + 1))
+        .map(|list| parse_ops::<N>(list).unwrap());
+
+    if args.first().map(String::as_str) == Some("explain") {
+        let ops = ops.unwrap_or_else(|| parse_ops(PART_1_OPS).unwrap());
+        for eq in &eqs {
+            if args.iter().any(|a| a == "--count") {
+                println!(
+                    "{}: {} solutions",
+                    eq.result,
+                    eq.count_solutions(&ops, solver)
+                );
+            } else if args.iter().any(|a| a == "--all") {
+                let solutions = eq.solutions(&ops, solver);
+                if solutions.is_empty() {
+                    println!("{}: no solution", eq.result);
+                }
+                for solution in solutions {
+                    println!("{} = {}", eq.result, eq.expression(&solution));
+                }
+            } else {
+                match eq.first_solution(&ops, solver) {
+                    Some(solution) => println!("{} = {}", eq.result, eq.expression(&solution)),
+                    None => println!("{}: no solution", eq.result),
+                }
+            }
+        }
+        return;
+    }
+
+    // The sums are kept in arbitrary precision, as they can overflow even when every
+    // equation fits.
+    let op_sets: Vec<Vec<OpSpec<N>>> = match ops {
+        Some(ops) => vec![ops],
+        None => vec![
+            parse_ops(PART_1_OPS).unwrap(),
+            parse_ops(PART_2_OPS).unwrap(),
+        ],
+    };
+    for ops in op_sets {
+        let result: BigInt = eqs
+            .iter()
+            .filter(|e| e.first_solution(&ops, solver).is_some())
+            .map(|e| e.result.clone().into())
+            .sum();
+        println!("{result}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solvers_agree() {
+        let input = r#"190: 10 19
+3267: 81 40 27
+83: 17 5
+156: 15 6
+7290: 6 8 6 15
+161011: 16 10 13
+192: 17 8 14
+21037: 9 7 18 13
+292: 11 6 16 20
+0: 5 0 3
+-12: 4 8 2
+64: 2 3 2
+-8: 2 3 0 3
+12: -1 3 2 4 4"#;
+
+        for ops in [PART_1_OPS, PART_2_OPS, "+,*,||,-,/,^"] {
+            let ops = parse_ops(ops).unwrap();
+            for eq in parse_equations::<i64>(input).unwrap() {
+                assert_eq!(
+                    eq.count_solutions(&ops, Solver::Forward),
+                    eq.count_solutions(&ops, Solver::Inverse),
+                    "{eq:?}"
+                );
+            }
+        }
+    }
 }
@@ -1,13 +1,90 @@
-use std::fs;
+use std::{
+    collections::{BTreeMap, HashSet},
+    env, fs,
+};
 
 fn is_antenna(b: u8) -> bool {
     b.is_ascii_alphabetic() || b.is_ascii_digit()
 }
 
+const ANTENNA_COLOUR: &str = "\x1b[36m";
+const ANTINODE_COLOUR: &str = "\x1b[1;33m";
+const COINCIDENT_COLOUR: &str = "\x1b[1;30;43m";
+const RESET_COLOUR: &str = "\x1b[0m";
+
+/// How many gaps beyond each antenna of a pair harmonics are placed at.
+struct Harmonics {
+    min: i32,
+    /// `None` to keep going until the edge of the map.
+    max: Option<i32>,
+}
+
+/// Where antinodes are placed along the line through a pair of antennas with the same
+/// frequency.
+struct Rule {
+    /// Places antinodes where one antenna is `ratio` times as far away as the other. Besides
+    /// the two outside the pair, there are two between them when they land on tiles.
+    ratio: Option<i32>,
+    /// Places antinodes at whole multiples of the gap beyond either antenna.
+    harmonics: Option<Harmonics>,
+    /// Also places harmonics on every tile in line between the antennas, and on the tiles
+    /// between each multiple of the gap. Has no effect without `harmonics`.
+    lattice: bool,
+}
+
+const PART_1: Rule = Rule {
+    ratio: Some(2),
+    harmonics: None,
+    lattice: false,
+};
+
+const PART_2: Rule = Rule {
+    ratio: None,
+    harmonics: Some(Harmonics { min: 0, max: None }),
+    lattice: false,
+};
+
+impl Harmonics {
+    /// Parses a range of multiples like `1..3`, or `1..` to go to the edge of the map.
+    fn from_str(s: &str) -> Self {
+        let (min, max) = s.split_once("..").unwrap();
+        Self {
+            min: min.parse().unwrap(),
+            max: (!max.is_empty()).then(|| max.parse().unwrap()),
+        }
+    }
+}
+
+/// The fractions of the gap from one antenna to the other where the other is `ratio` times as
+/// far away as the first, or the other way round.
+fn ratio_fractions(ratio: i32) -> Vec<(i32, i32)> {
+    assert!(ratio >= 1, "The distance ratio must be at least 1!");
+    if ratio == 1 {
+        return vec![(1, 2)];
+    }
+
+    vec![
+        (ratio, ratio - 1),
+        (-1, ratio - 1),
+        (ratio, ratio + 1),
+        (1, ratio + 1),
+    ]
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
 struct Grid {
     data: Vec<Vec<u8>>,
     width: i32,
     height: i32,
+    /// The positions of the antennas, grouped by frequency.
+    antennas: BTreeMap<u8, Vec<(i32, i32)>>,
 }
 
 impl Grid {
@@ -15,10 +92,21 @@ impl Grid {
         let data: Vec<Vec<u8>> = s.lines().map(|line| line.bytes().collect()).collect();
         let height = data.len() as i32;
         let width = data.first().map(|row| row.len()).unwrap_or(0) as i32;
+
+        let mut antennas: BTreeMap<u8, Vec<(i32, i32)>> = BTreeMap::new();
+        for (y, row) in data.iter().enumerate() {
+            for (x, v) in row.iter().enumerate() {
+                if is_antenna(*v) {
+                    antennas.entry(*v).or_default().push((x as i32, y as i32));
+                }
+            }
+        }
+
         Self {
             data,
             width,
             height,
+            antennas,
         }
     }
 
@@ -32,82 +120,109 @@ impl Grid {
         }
     }
 
-    fn has_value_at_distance(&self, x: i32, y: i32, value: u8, distance: (i32, i32)) -> bool {
-        [
-            (x + distance.0, y + distance.1),
-            (x - distance.0, y - distance.1),
-        ]
-        .into_iter()
-        .any(|p| self.at(p.0, p.1).is_some_and(|v| v == value))
+    fn contains(&self, p: (i32, i32)) -> bool {
+        p.0 >= 0 && p.0 < self.width && p.1 >= 0 && p.1 < self.height
     }
 
-    fn is_antinode_1(&self, x: i32, y: i32) -> bool {
-        for x1 in 0..self.width {
-            for y1 in 0..self.height {
-                let v = self.at(x1, y1).unwrap();
-                let other_dist = ((x1 - x) * 2, (y1 - y) * 2);
-                if other_dist != (0, 0)
-                    && is_antenna(v)
-                    && self.has_value_at_distance(x, y, v, other_dist)
-                {
-                    return true;
+    /// Every pair of antennas sharing a frequency, each pair once, optionally only for one
+    /// frequency.
+    fn pairs(&self, frequency: Option<u8>) -> impl Iterator<Item = ((i32, i32), (i32, i32))> + '_ {
+        self.antennas
+            .iter()
+            .filter(move |(f, _)| frequency.is_none_or(|frequency| **f == frequency))
+            .flat_map(|(_, positions)| {
+                positions
+                    .iter()
+                    .enumerate()
+                    .flat_map(move |(i, a)| positions[i + 1..].iter().map(move |b| (*a, *b)))
+            })
+    }
+
+    /// The antinodes `rule` places along the line through a pair of antennas.
+    fn antinodes_for_pair(&self, a: (i32, i32), b: (i32, i32), rule: &Rule) -> Vec<(i32, i32)> {
+        let dist = (b.0 - a.0, b.1 - a.1);
+        let mut antinodes = Vec::new();
+
+        // Each point is `a` plus a fraction of the gap, and only counts if it lands on a tile.
+        if let Some(ratio) = rule.ratio {
+            for (num, den) in ratio_fractions(ratio) {
+                if (dist.0 * num) % den == 0 && (dist.1 * num) % den == 0 {
+                    antinodes.push((a.0 + dist.0 * num / den, a.1 + dist.1 * num / den));
                 }
             }
         }
 
-        false
-    }
+        if let Some(harmonics) = &rule.harmonics {
+            // With lattice points, each gap is walked in the smallest whole steps along it.
+            let steps = if rule.lattice {
+                gcd(dist.0.abs(), dist.1.abs())
+            } else {
+                1
+            };
+            let step = (dist.0 / steps, dist.1 / steps);
+
+            for (from, step) in [(b, step), (a, (-step.0, -step.1))] {
+                let mut n = harmonics.min * steps;
+                while harmonics.max.is_none_or(|max| n <= max * steps) {
+                    let p = (from.0 + step.0 * n, from.1 + step.1 * n);
+                    if !self.contains(p) {
+                        break;
+                    }
+                    antinodes.push(p);
+                    n += 1;
+                }
+            }
 
-    fn count_antinodes_2(&self) -> usize {
-        let mut antinode_grid = self.data.clone();
-        for x in 0..self.width {
-            for y in 0..self.height {
-                self.mark_antinodes_2(x, y, &mut antinode_grid);
+            if rule.lattice {
+                antinodes.extend((1..steps).map(|n| (a.0 + step.0 * n, a.1 + step.1 * n)));
             }
         }
 
-        antinode_grid
-            .iter()
-            .map(|row| row.iter().copied().filter(|b| *b == b'%').count())
-            .sum()
+        antinodes
+            .into_iter()
+            .filter(|p| self.at(p.0, p.1).is_some())
+            .collect()
     }
 
-    fn mark_antinodes_2(&self, x: i32, y: i32, grid: &mut [Vec<u8>]) {
-        let Some(v) = self.at(x, y) else {
-            return;
-        };
+    fn antinodes(&self, rule: &Rule, frequency: Option<u8>) -> HashSet<(i32, i32)> {
+        self.pairs(frequency)
+            .flat_map(|(a, b)| self.antinodes_for_pair(a, b, rule))
+            .collect()
+    }
 
-        if !is_antenna(v) {
-            return;
-        }
+    /// Renders the map with antinodes marked as `#`. Antennas keep their frequency, and ones
+    /// with an antinode on them are highlighted, or shown as `*` when plain.
+    fn render(&self, antinodes: &HashSet<(i32, i32)>, plain: bool) -> String {
+        let mut output = String::new();
+        for (y, row) in self.data.iter().enumerate() {
+            for (x, &v) in row.iter().enumerate() {
+                let antinode = antinodes.contains(&(x as i32, y as i32));
+                let (colour, cell) = match (is_antenna(v), antinode, plain) {
+                    (true, true, true) => ("", '*'),
+                    (true, true, false) => (COINCIDENT_COLOUR, v as char),
+                    (true, false, _) => (ANTENNA_COLOUR, v as char),
+                    (false, true, _) => (ANTINODE_COLOUR, '#'),
+                    (false, false, _) => ("", v as char),
+                };
 
-        for x1 in 0..self.width {
-            for y1 in 0..self.height {
-                if (x1, y1) != (x, y) && self.at(x1, y1).is_some_and(|v1| v1 == v) {
-                    self.mark_antinodes_for_pair_2((x, y), (x1, y1), grid);
+                if plain || colour.is_empty() {
+                    output.push(cell);
+                } else {
+                    output.push_str(colour);
+                    output.push(cell);
+                    output.push_str(RESET_COLOUR);
                 }
             }
+            output.push('\n');
         }
-    }
-
-    fn mark_antinodes_for_pair_2(&self, a1: (i32, i32), a2: (i32, i32), grid: &mut [Vec<u8>]) {
-        let dist = (a2.0 - a1.0, a2.1 - a1.1);
-        let mut mark_dist: (i32, i32) = (0, 0);
-        while mark_dist.0.abs() < self.width && mark_dist.1.abs() < self.height {
-            let mx = a1.0 - mark_dist.0;
-            let my = a1.1 - mark_dist.1;
-            if self.at(mx, my).is_some() {
-                grid[my as usize][mx as usize] = b'%';
-            }
 
-            let mx = a2.0 + mark_dist.0;
-            let my = a2.1 + mark_dist.1;
-            if self.at(mx, my).is_some() {
-                grid[my as usize][mx as usize] = b'%';
-            }
+        let coincident = antinodes
+            .iter()
+            .filter(|(x, y)| self.at(*x, *y).is_some_and(is_antenna))
+            .count();
+        output += &format!("{} antinodes, {coincident} on antennas\n", antinodes.len());
 
-            mark_dist = (mark_dist.0 + dist.0, mark_dist.1 + dist.1);
-        }
+        output
     }
 }
 
@@ -115,15 +230,70 @@ fn main() {
     let input = fs::read_to_string("input/input.txt").unwrap();
     let grid = Grid::from_str(&input);
 
-    let result: usize = (0..grid.width)
-        .map(|x| {
-            (0..grid.height)
-                .filter(|y| grid.is_antinode_1(x, *y))
-                .count()
-        })
-        .sum();
+    // Usage: day_8 [render [--part 1|2] [--frequency <char>] [--plain]]
+    //              [--ratio <n>] [--harmonics <min>..[<max>]] [--lattice]
+    // Giving any of the rule options replaces both parts with that rule. `--lattice` without
+    // `--harmonics` uses the harmonics from part 2.
+    let args: Vec<String> = env::args().skip(1).collect();
+    let option = |name: &str| {
+        args.iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 1))
+    };
+
+    let lattice = args.iter().any(|a| a == "--lattice");
+    let custom =
+        (option("--ratio").is_some() || option("--harmonics").is_some() || lattice).then(|| Rule {
+            ratio: option("--ratio").map(|r| r.parse().unwrap()),
+            harmonics: option("--harmonics")
+                .map(|h| Harmonics::from_str(h))
+                .or_else(|| lattice.then_some(Harmonics { min: 0, max: None })),
+            lattice,
+        });
+
+    if args.first().map(String::as_str) == Some("render") {
+        let frequency = option("--frequency").map(|f| f.as_bytes()[0]);
+        let rule = match (&custom, option("--part").map_or("1", String::as_str)) {
+            (Some(rule), _) => rule,
+            (None, "1") => &PART_1,
+            (None, "2") => &PART_2,
+            (None, part) => panic!("Unknown part {part}"),
+        };
+        let antinodes = grid.antinodes(rule, frequency);
+
+        print!(
+            "{}",
+            grid.render(&antinodes, args.iter().any(|a| a == "--plain"))
+        );
+        return;
+    }
+
+    if let Some(rule) = &custom {
+        let result = grid.antinodes(rule, None).len();
+        println!("{result}");
+        return;
+    }
+
+    let result = grid.antinodes(&PART_1, None).len();
     println!("{result}");
 
-    let result: usize = grid.count_antinodes_2();
+    let result = grid.antinodes(&PART_2, None).len();
     println!("{result}");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lattice() {
+        let grid = Grid::from_str(".....\n.a...\n.....\n...a.\n.....");
+        let rule = Rule {
+            lattice: true,
+            ..PART_2
+        };
+
+        assert_eq!(grid.antinodes(&PART_2, None).len(), 2);
+        assert_eq!(grid.antinodes(&rule, None).len(), 5);
+    }
+}
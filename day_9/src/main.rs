@@ -1,138 +1,328 @@
-use std::fs;
+use std::{cmp::Reverse, collections::BinaryHeap, env, fs};
+
+/// The longest span a disk map can describe, as each length is a single digit.
+const MAX_SPAN: usize = 9;
 
 #[derive(Debug, Clone, Copy)]
-enum Contents {
-    Free,
-    File(usize),
+struct Span {
+    start: usize,
+    length: usize,
+}
+
+impl Span {
+    /// The same span on the disk read back to front.
+    fn mirrored(&self, size: usize) -> Self {
+        Self {
+            start: size - self.start - self.length,
+            length: self.length,
+        }
+    }
 }
 
+/// A piece of a file and where it is on the disk.
 #[derive(Debug, Clone, Copy)]
-struct Block {
-    length: usize,
-    contents: Contents,
+struct Extent {
+    id: usize,
+    span: Span,
 }
 
-fn compacted(disk: &[Block]) -> Vec<Block> {
-    let mut disk = disk.to_vec();
-
-    let mut front: usize = 0;
-    let mut back: usize = 0;
-    while front < disk.len() && back < disk.len() && front + back < disk.len() - 1 {
-        let fi = front;
-        let bi = disk.len() - 1 - back;
-        match (disk[fi].contents, disk[bi].contents) {
-            (Contents::File(_), _) => {
-                front += 1;
-            }
-            (_, Contents::Free) => {
-                back += 1;
-            }
-            (Contents::Free, Contents::File(_)) if disk[fi].length < disk[bi].length => {
-                disk[bi].length -= disk[fi].length;
-                disk[fi].contents = disk[bi].contents;
-            }
-            (Contents::Free, Contents::File(_)) if disk[fi].length > disk[bi].length => {
-                let file_block = disk.remove(bi);
-                disk.insert(
-                    fi + 1,
-                    Block {
-                        length: disk[fi].length - file_block.length,
-                        contents: Contents::Free,
-                    },
-                );
-                disk[fi] = file_block;
-            }
-            (Contents::Free, Contents::File(_)) => {
-                disk[fi] = disk[bi];
-                disk.remove(bi);
+/// Where whole-file compaction moves each file to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Policy {
+    /// The leftmost free span that fits.
+    FirstFit,
+    /// The shortest free span that fits, leftmost first.
+    BestFit,
+    /// The longest free span that fits, leftmost first.
+    WorstFit,
+    /// The rightmost free span that fits, moving files from the lowest ID up to pack them
+    /// against the end of the disk.
+    TowardEnd,
+}
+
+impl Policy {
+    const ALL: [Policy; 4] = [
+        Policy::FirstFit,
+        Policy::BestFit,
+        Policy::WorstFit,
+        Policy::TowardEnd,
+    ];
+
+    fn from_string(s: &str) -> Self {
+        match s {
+            "first" => Policy::FirstFit,
+            "best" => Policy::BestFit,
+            "worst" => Policy::WorstFit,
+            "end" => Policy::TowardEnd,
+            _ => panic!("Unknown policy {s}"),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Policy::FirstFit => "first-fit",
+            Policy::BestFit => "best-fit",
+            Policy::WorstFit => "worst-fit",
+            Policy::TowardEnd => "toward end",
+        }
+    }
+}
+
+struct DiskMap {
+    /// Where each file is, indexed by its ID.
+    files: Vec<Span>,
+    /// The gaps between the files, from left to right.
+    free: Vec<Span>,
+}
+
+impl DiskMap {
+    fn from_str(s: &str) -> Self {
+        let mut files = Vec::new();
+        let mut free = Vec::new();
+        let mut start = 0;
+        for (i, c) in s.trim().chars().enumerate() {
+            let length = c.to_digit(10).unwrap() as usize;
+            let span = Span { start, length };
+            if i % 2 == 0 {
+                files.push(span);
+            } else if length > 0 {
+                free.push(span);
             }
+            start += length;
         }
+
+        Self { files, free }
     }
 
-    disk
+    fn size(&self) -> usize {
+        let last = |spans: &[Span]| spans.last().map_or(0, |s| s.start + s.length);
+        last(&self.files).max(last(&self.free))
+    }
+
+    /// The same disk read back to front, with the files keeping their IDs.
+    fn mirrored(&self) -> Self {
+        let size = self.size();
+        Self {
+            files: self.files.iter().map(|f| f.mirrored(size)).collect(),
+            free: self.free.iter().rev().map(|f| f.mirrored(size)).collect(),
+        }
+    }
 }
 
-fn compacted_2(disk: &[Block]) -> Vec<Block> {
-    let mut disk = disk.to_vec();
-
-    let mut back: usize = 0;
-    while back < disk.len() {
-        let bi = disk.len() - 1 - back;
-        match disk[bi].contents {
-            Contents::File(_) => {
-                for fi in 0..bi {
-                    if matches!(disk[fi].contents, Contents::Free)
-                        && disk[fi].length >= disk[bi].length
-                    {
-                        let remaining = disk[fi].length - disk[bi].length;
-                        disk[fi].length = disk[bi].length;
-                        disk.swap(fi, bi);
-                        if remaining > 0 {
-                            disk.insert(
-                                fi + 1,
-                                Block {
-                                    length: remaining,
-                                    contents: Contents::Free,
-                                },
-                            );
-                        }
-                        break;
-                    }
-                }
-                back += 1;
+/// Moves blocks one at a time from the end of the disk into the leftmost free blocks, until
+/// there are no gaps left between the files.
+fn compacted(disk: &DiskMap) -> Vec<Extent> {
+    let mut extents = Vec::new();
+    let mut files = disk.files.clone();
+
+    // Files from `back` onwards have been moved completely. The last file left keeps its
+    // start as blocks are taken off its end.
+    let mut back = files.len();
+    'free: for free in &disk.free {
+        let mut free = *free;
+        while free.length > 0 {
+            while back > 0 && files[back - 1].length == 0 {
+                back -= 1;
             }
-            Contents::Free => {
-                back += 1;
+            if back == 0 || files[back - 1].start < free.start {
+                break 'free;
             }
+
+            let file = &mut files[back - 1];
+            let length = free.length.min(file.length);
+            extents.push(Extent {
+                id: back - 1,
+                span: Span {
+                    start: free.start,
+                    length,
+                },
+            });
+            free.start += length;
+            free.length -= length;
+            file.length -= length;
         }
     }
 
-    disk
+    extents.extend(
+        files[..back]
+            .iter()
+            .enumerate()
+            .map(|(id, span)| Extent { id, span: *span }),
+    );
+
+    extents
 }
 
-fn compute_checksum(disk: &[Block]) -> usize {
-    let mut result = 0;
-    let mut pos = 0;
-    for block in disk {
-        if let Contents::File(id) = block.contents {
-            result += (pos..pos + block.length).map(|p| p * id).sum::<usize>();
+/// Moves each file once, from the rightmost down, into a free span before it that fits the
+/// whole file, chosen by `policy`.
+fn compacted_2(disk: &DiskMap, policy: Policy) -> Vec<Extent> {
+    if policy == Policy::TowardEnd {
+        // Packing toward the end is packing the mirrored disk toward its start.
+        let size = disk.size();
+        return compacted_2(&disk.mirrored(), Policy::FirstFit)
+            .into_iter()
+            .map(|Extent { id, span }| Extent {
+                id,
+                span: span.mirrored(size),
+            })
+            .collect();
+    }
+
+    // The starts of the free spans, with one min-heap for each length. The leftmost span that
+    // fits is the smallest start at the top of any heap long enough. The space a file leaves
+    // behind is never indexed, as every file still to move is further left.
+    let mut spans: [BinaryHeap<Reverse<usize>>; MAX_SPAN + 1] = Default::default();
+    for free in &disk.free {
+        spans[free.length].push(Reverse(free.start));
+    }
+
+    let mut order: Vec<usize> = (0..disk.files.len()).collect();
+    order.sort_by_key(|id| Reverse(disk.files[*id].start));
+
+    let mut extents = Vec::with_capacity(disk.files.len());
+    for id in order {
+        let file = &disk.files[id];
+        let mut fits = (file.length.max(1)..=MAX_SPAN)
+            .filter_map(|length| spans[length].peek().map(|Reverse(start)| (*start, length)))
+            .filter(|(start, _)| *start < file.start);
+        let chosen = match policy {
+            Policy::FirstFit => fits.min(),
+            Policy::BestFit => fits.next(),
+            Policy::WorstFit => fits.next_back(),
+            Policy::TowardEnd => unreachable!(),
+        };
+
+        let Some((start, length)) = chosen else {
+            extents.push(Extent { id, span: *file });
+            continue;
+        };
+
+        spans[length].pop();
+        if length > file.length {
+            spans[length - file.length].push(Reverse(start + file.length));
         }
-        pos += block.length;
+        extents.push(Extent {
+            id,
+            span: Span {
+                start,
+                length: file.length,
+            },
+        });
     }
 
-    result
+    extents
+}
+
+fn compute_checksum(extents: &[Extent]) -> usize {
+    extents
+        .iter()
+        .map(|Extent { id, span }| {
+            // The sum of the positions from `start` to `start + length - 1`.
+            let positions =
+                span.length * span.start + span.length * span.length.saturating_sub(1) / 2;
+            id * positions
+        })
+        .sum()
+}
+
+/// How a disk ended up after compaction.
+struct Report {
+    checksum: usize,
+    /// The number of free spans left between files.
+    fragmentation: usize,
+    /// The total length of the file pieces that aren't where they started.
+    bytes_moved: usize,
+}
+
+impl Report {
+    fn new(disk: &DiskMap, extents: &[Extent]) -> Self {
+        let mut spans: Vec<Span> = extents
+            .iter()
+            .map(|extent| extent.span)
+            .filter(|span| span.length > 0)
+            .collect();
+        spans.sort_by_key(|span| span.start);
+
+        Self {
+            checksum: compute_checksum(extents),
+            fragmentation: spans
+                .windows(2)
+                .filter(|pair| pair[0].start + pair[0].length < pair[1].start)
+                .count(),
+            bytes_moved: extents
+                .iter()
+                .filter(|extent| extent.span.start != disk.files[extent.id].start)
+                .map(|extent| extent.span.length)
+                .sum(),
+        }
+    }
 }
 
 fn main() {
     let input = fs::read_to_string("input/input.txt").unwrap();
+    let disk = DiskMap::from_str(&input);
 
-    let disk: Vec<Block> = input
-        .trim()
-        .chars()
-        .enumerate()
-        .filter_map(|(i, c)| {
-            let digit = c.to_digit(10).unwrap() as usize;
-            if digit == 0 {
-                return None;
-            }
+    // Usage: day_9 [policies [first|best|worst|end]...]
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("policies") {
+        let policies: Vec<Policy> = if args.len() > 1 {
+            args[1..].iter().map(|p| Policy::from_string(p)).collect()
+        } else {
+            Policy::ALL.to_vec()
+        };
 
-            let contents = if i % 2 == 0 {
-                Contents::File(i / 2)
-            } else {
-                Contents::Free
-            };
-            Some(Block {
-                length: digit,
-                contents,
-            })
-        })
-        .collect();
+        for policy in policies {
+            let report = Report::new(&disk, &compacted_2(&disk, policy));
+            println!(
+                "{}: checksum {}, {} free spans, {} bytes moved",
+                policy.label(),
+                report.checksum,
+                report.fragmentation,
+                report.bytes_moved
+            );
+        }
+        return;
+    }
 
     let compacted = compacted(&disk);
     let result = compute_checksum(&compacted);
     println!("{result}");
 
-    let compacted = compacted_2(&disk);
+    let compacted = compacted_2(&disk, Policy::FirstFit);
     let result = compute_checksum(&compacted);
     println!("{result}");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "2333133121414131402";
+
+    #[test]
+    fn test_sample() {
+        let disk = DiskMap::from_str(SAMPLE);
+
+        assert_eq!(compute_checksum(&compacted(&disk)), 1928);
+        assert_eq!(
+            compute_checksum(&compacted_2(&disk, Policy::FirstFit)),
+            2858
+        );
+    }
+
+    #[test]
+    fn test_policies() {
+        // Best-fit fills the one-block gap next to the last file instead of the wider one at
+        // the start, leaving a gap behind.
+        let disk = DiskMap::from_str("13111");
+        let report = Report::new(&disk, &compacted_2(&disk, Policy::BestFit));
+        assert_eq!(report.checksum, 11);
+        assert_eq!(report.fragmentation, 1);
+        assert_eq!(report.bytes_moved, 2);
+
+        let disk = DiskMap::from_str(SAMPLE);
+        let report = Report::new(&disk, &compacted_2(&disk, Policy::TowardEnd));
+        assert_eq!(report.checksum, 4173);
+        assert_eq!(report.bytes_moved, 6);
+    }
+}